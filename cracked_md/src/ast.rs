@@ -1,7 +1,10 @@
 //! Abstract syntax tree of "Markdown".
 
+use std::{collections::BTreeMap, fmt::Display, ops::RangeInclusive};
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Document {
+    pub metadata: Metadata,
     pub blocks: Vec<Block>,
 }
 
@@ -11,13 +14,163 @@ pub enum Block {
     Heading {
         level: u8,
         content: Vec<Inline>,
+        /// Unique slug of the heading text, used as the anchor `id`.
+        id: String,
     },
     Code {
         language: Option<String>,
         content: String,
+        /// `key="value"` pairs after the language in the fence info string, bare keys with an
+        /// empty value. `title`, `linenos` and `hl_lines` change how the block is rendered.
+        attributes: BTreeMap<String, String>,
+    },
+    List(Vec<ListItem>),
+    OrderedList {
+        start: u64,
+        items: Vec<ListItem>,
     },
-    List(Vec<Block>),
     Quote(Vec<Block>),
+    Table {
+        header: Vec<Vec<Inline>>,
+        alignments: Vec<Alignment>,
+        rows: Vec<Vec<Vec<Inline>>>,
+    },
+    /// Table of contents, from a `[TOC]` marker.
+    Toc(Vec<TocEntry>),
+    /// A horizontal rule, like `***` or `---` in CommonMark.
+    ThematicBreak,
+    /// The footnotes of the document in the order of their numbers, put at its end.
+    Footnotes(Vec<Footnote>),
+}
+
+impl Document {
+    /// Builds a table of contents from the headings, in nested lists, quotes and footnotes too.
+    /// Each heading is nested under the closest preceding heading of a lower level.
+    #[must_use]
+    pub fn toc(&self) -> Vec<TocEntry> {
+        let mut toc = Vec::new();
+        collect_toc(&self.blocks, &mut toc);
+        toc
+    }
+
+    /// Counts the open and done tasks of the task lists, in nested lists, quotes and footnotes
+    /// too.
+    #[must_use]
+    pub fn tasks(&self) -> Tasks {
+        let mut tasks = Tasks::default();
+        count_tasks(&self.blocks, &mut tasks);
+        tasks
+    }
+}
+
+/// Numbers of tasks in a document, see [`Document::tasks`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tasks {
+    pub open: usize,
+    pub done: usize,
+}
+
+impl Tasks {
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.open + self.done
+    }
+}
+
+fn collect_toc(blocks: &[Block], toc: &mut Vec<TocEntry>) {
+    for block in blocks {
+        match block {
+            Block::Heading { level, content, id } => {
+                let entry = TocEntry {
+                    level: *level,
+                    text: plain_text(content),
+                    id: id.clone(),
+                    children: Vec::new(),
+                };
+                insert_toc_entry(toc, entry);
+            }
+            Block::Quote(blocks) => collect_toc(blocks, toc),
+            Block::List(items) | Block::OrderedList { items, .. } => {
+                for item in items {
+                    collect_toc(&item.blocks, toc);
+                }
+            }
+            Block::Footnotes(footnotes) => {
+                for footnote in footnotes {
+                    collect_toc(&footnote.blocks, toc);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn count_tasks(blocks: &[Block], tasks: &mut Tasks) {
+    for block in blocks {
+        match block {
+            Block::Quote(blocks) => count_tasks(blocks, tasks),
+            Block::List(items) | Block::OrderedList { items, .. } => {
+                for item in items {
+                    match item.checked {
+                        Some(true) => tasks.done += 1,
+                        Some(false) => tasks.open += 1,
+                        None => {}
+                    }
+                    count_tasks(&item.blocks, tasks);
+                }
+            }
+            Block::Footnotes(footnotes) => {
+                for footnote in footnotes {
+                    count_tasks(&footnote.blocks, tasks);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn insert_toc_entry(entries: &mut Vec<TocEntry>, entry: TocEntry) {
+    match entries.last_mut() {
+        Some(last) if last.level < entry.level => insert_toc_entry(&mut last.children, entry),
+        _ => entries.push(entry),
+    }
+}
+
+/// A heading in the table of contents, with the headings under it as children.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry {
+    pub level: u8,
+    pub text: String,
+    pub id: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// A single list item, which may hold any blocks (including nested lists).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    pub blocks: Vec<Block>,
+    /// Whether the task is done, if the item is a task starting with `[ ]` or `[x]`.
+    pub checked: Option<bool>,
+}
+
+/// A footnote, from a `[^label]: text` definition, which may continue on indented lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Footnote {
+    pub label: String,
+    /// Number of the footnote, in the order of the first references to the footnotes.
+    pub number: usize,
+    pub blocks: Vec<Block>,
+    /// Number of references to the footnote, each of which gets a link back.
+    pub references: usize,
+}
+
+/// Text alignment of a table column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -25,6 +178,275 @@ pub enum Inline {
     Text(String),
     Bold(Vec<Inline>),
     Italic(Vec<Inline>),
+    Strikethrough(Vec<Inline>),
+    /// Marked text, like `==this==`.
+    Highlight(Vec<Inline>),
+    Subscript(Vec<Inline>),
+    Superscript(Vec<Inline>),
+    /// A keyboard key, like `[[Ctrl]]`.
+    Kbd(String),
     Code(String),
-    Link { text: Vec<Inline>, href: String },
+    Link {
+        text: Vec<Inline>,
+        href: String,
+        title: Option<String>,
+    },
+    Image {
+        alt: String,
+        src: String,
+        title: Option<String>,
+    },
+    LineBreak,
+    /// A reference to a footnote, like `[^label]`.
+    FootnoteRef {
+        label: String,
+        /// Number of the footnote, see [`Footnote::number`].
+        number: usize,
+        /// Which of the references to the footnote this is, counted from 1.
+        occurrence: usize,
+    },
+}
+
+/// Concatenates the text of inline elements, dropping all formatting.
+#[must_use]
+pub fn plain_text(inlines: &[Inline]) -> String {
+    let mut text = String::new();
+    for inline in inlines {
+        match inline {
+            Inline::Text(s) | Inline::Code(s) | Inline::Kbd(s) => text.push_str(s),
+            Inline::Bold(content)
+            | Inline::Italic(content)
+            | Inline::Strikethrough(content)
+            | Inline::Highlight(content)
+            | Inline::Subscript(content)
+            | Inline::Superscript(content)
+            | Inline::Link { text: content, .. } => {
+                text.push_str(&plain_text(content));
+            }
+            Inline::Image { alt, .. } => text.push_str(alt),
+            Inline::LineBreak => text.push('\n'),
+            Inline::FootnoteRef { .. } => {}
+        }
+    }
+    text
+}
+
+/// Parses line numbers and ranges like `1 3-5` or `1,3-5`, as in the `hl_lines` attribute of
+/// code blocks. `None` if any part is not a number or a range, or a range is reversed like `5-3`.
+#[must_use]
+pub fn parse_line_ranges(s: &str) -> Option<Vec<RangeInclusive<usize>>> {
+    s.split([',', ' '])
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (start, end) = part.split_once('-').unwrap_or((part, part));
+            let range = start.trim().parse().ok()?..=end.trim().parse().ok()?;
+            (!range.is_empty()).then_some(range)
+        })
+        .collect()
+}
+
+/// A place in the source text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    /// Byte offset from the start of the source.
+    pub offset: usize,
+    /// Line, counted from 1.
+    pub line: usize,
+    /// Column in characters, counted from 1.
+    pub col: usize,
+}
+
+/// The part of the source a node was parsed from, the end exclusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// The span of a node with the spans of the nodes inside it, in the same order as in the AST:
+/// the inlines of paragraphs, headings, formatting and links, the blocks of quotes, the items of
+/// lists and their blocks, the header cells and then the row cells of tables with their
+/// inlines, and the definitions of footnotes with their blocks. Code blocks, tables of contents,
+/// thematic breaks, and text, code, keys, images, line breaks and footnote references have none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpanTree {
+    pub span: Span,
+    pub children: Vec<SpanTree>,
+}
+
+/// Page metadata from the front matter, keyed by name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata(BTreeMap<String, Value>);
+
+impl Metadata {
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0.get(key)
+    }
+
+    /// Sets a value, returning the previous value of the key.
+    pub fn insert(&mut self, key: impl ToString, value: Value) -> Option<Value> {
+        self.0.insert(key.to_string(), value)
+    }
+
+    /// Removes a key, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.0.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.0.iter()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[must_use]
+    pub fn title(&self) -> Option<&str> {
+        self.get("title").and_then(Value::as_str)
+    }
+
+    #[must_use]
+    pub fn description(&self) -> Option<&str> {
+        self.get("description").and_then(Value::as_str)
+    }
+
+    #[must_use]
+    pub fn date(&self) -> Option<Date> {
+        self.get("date").and_then(Value::as_date)
+    }
+
+    /// String values of the `tags` array, empty if there are none.
+    #[must_use]
+    pub fn tags(&self) -> Vec<&str> {
+        self.get("tags")
+            .and_then(Value::as_array)
+            .map(|tags| tags.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether the page is marked as a draft, `false` if not set.
+    #[must_use]
+    pub fn draft(&self) -> bool {
+        self.get("draft").and_then(Value::as_bool).unwrap_or(false)
+    }
+}
+
+/// A front matter value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Date(Date),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_date(&self) -> Option<Date> {
+        match self {
+            Self::Date(d) => Some(*d),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "{s}"),
+            Self::Integer(i) => write!(f, "{i}"),
+            Self::Boolean(b) => write!(f, "{b}"),
+            Self::Date(d) => write!(f, "{d}"),
+            Self::Array(a) => {
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{v}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A calendar date, displayed as `YYYY-MM-DD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    /// Parses a `YYYY-MM-DD` date, checking that the day exists.
+    #[must_use]
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('-');
+        let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some()
+            || year.len() != 4
+            || month.len() != 2
+            || day.len() != 2
+            || !s.bytes().all(|b| b.is_ascii_digit() || b == b'-')
+        {
+            return None;
+        }
+        let date = Self {
+            year: year.parse().ok()?,
+            month: month.parse().ok()?,
+            day: day.parse().ok()?,
+        };
+        let leap = date.year % 4 == 0 && (date.year % 100 != 0 || date.year % 400 == 0);
+        let days = match date.month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if leap => 29,
+            2 => 28,
+            _ => return None,
+        };
+        (1..=days).contains(&date.day).then_some(date)
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
 }
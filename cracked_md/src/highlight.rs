@@ -0,0 +1,512 @@
+//! Syntax highlighting of code blocks, with grammars defined in files in the TOML subset of the
+//! front matter. Rules are tried in this order at every position of the code:
+//!
+//! ```toml
+//! name = "rust"
+//! aliases = ["rs"]
+//! # [class, prefix]: lines starting with the prefix, after indentation
+//! lines = [["heading", "#"]]
+//! # [class, start, end]: from `start` to `end`, or to the end of the line if `end` is "\n"
+//! regions = [["comment", "//", "\n"], ["comment", "/*", "*/"]]
+//! # quotes of strings with backslash escapes, in the class `string`
+//! strings = ['"']
+//! # quotes of character literals like `'x'` or `'\n'`, in the class `string`
+//! chars = ["'"]
+//! # [class, prefix]: the prefix and the word after it, like `$HOME`
+//! prefixes = [["variable", "$"]]
+//! # numbers like `1_000`, `0xff` or `1.5e3`, in the class `number`
+//! numbers = true
+//! # characters besides letters, digits and `_` that are part of words
+//! word_chars = "-"
+//! # words in the classes `keyword`, `type`, `literal` and `builtin`
+//! keywords = ["fn", "let"]
+//! types = ["u8", "String"]
+//! literals = ["true", "false"]
+//! builtins = ["println"]
+//! ```
+//!
+//! Tokens become `<span class="…">`, everything else is plain escaped text.
+
+use std::{collections::HashMap, fs, path::PathBuf, sync::LazyLock};
+
+use fstools::crawl_fs;
+
+use crate::{
+    Error, MdParseError,
+    ast::Value,
+    parser::parse_metadata,
+    to_html::{escape_attr, escape_text},
+};
+
+/// Definitions of the grammars that come with gravel.
+const BUILTIN: [&str; 8] = [
+    include_str!("highlight/rust.toml"),
+    include_str!("highlight/shell.toml"),
+    include_str!("highlight/toml.toml"),
+    include_str!("highlight/json.toml"),
+    include_str!("highlight/python.toml"),
+    include_str!("highlight/html.toml"),
+    include_str!("highlight/css.toml"),
+    include_str!("highlight/markdown.toml"),
+];
+
+/// Keys of the word lists in a definition, with the class of their words.
+const WORD_CLASSES: [(&str, &str); 4] = [
+    ("keywords", "keyword"),
+    ("types", "type"),
+    ("literals", "literal"),
+    ("builtins", "builtin"),
+];
+
+/// The rules for highlighting one language, see the [module docs](self) for the definition.
+#[derive(Debug, Clone, Default)]
+pub struct Grammar {
+    pub name: String,
+    pub aliases: Vec<String>,
+    lines: Vec<(String, String)>,
+    regions: Vec<(String, String, String)>,
+    strings: Vec<String>,
+    chars: Vec<String>,
+    prefixes: Vec<(String, String)>,
+    numbers: bool,
+    word_chars: Vec<char>,
+    words: HashMap<String, &'static str>,
+}
+
+impl Grammar {
+    /// Reads a grammar from its definition.
+    ///
+    /// # Errors
+    /// The definition is not valid TOML, has no `name`, a value has the wrong type or a
+    /// delimiter is empty.
+    pub fn parse(definition: &str) -> Result<Self, MdParseError> {
+        let fields = parse_metadata(definition)?;
+        let invalid = |key: &str, expected: &str| {
+            // point to the line of the key
+            let line = definition.lines().position(|l| {
+                l.trim_start()
+                    .strip_prefix(key)
+                    .is_some_and(|rest| rest.trim_start().starts_with('='))
+            });
+            MdParseError::from_line(
+                line.map_or(0, |i| i + 1),
+                format!("{expected} for `{key}`"),
+                line.and_then(|i| definition.lines().nth(i))
+                    .and_then(|l| l.split_once('='))
+                    .map_or("", |(_, value)| value.trim()),
+            )
+        };
+        // arrays of `len` strings, or of strings if `len` is 0
+        let arrays = |key: &str, len: usize| -> Result<Vec<Vec<String>>, MdParseError> {
+            let expected = match len {
+                0 => "an array of strings".to_string(),
+                _ => format!("an array of arrays of {len} strings"),
+            };
+            let Some(value) = fields.get(key) else {
+                return Ok(Vec::new());
+            };
+            let items = value.as_array().ok_or_else(|| invalid(key, &expected))?;
+            items
+                .iter()
+                .map(|item| {
+                    let strings = match (item, len) {
+                        (Value::String(s), 0) => Some(vec![s.clone()]),
+                        (Value::Array(parts), _) if parts.len() == len => parts
+                            .iter()
+                            .map(|p| p.as_str().map(str::to_string))
+                            .collect(),
+                        _ => None,
+                    };
+                    strings.ok_or_else(|| invalid(key, &expected))
+                })
+                .collect()
+        };
+        let pairs = |key: &str| -> Result<Vec<(String, String)>, MdParseError> {
+            Ok(arrays(key, 2)?
+                .into_iter()
+                .map(|p| (p[0].clone(), p[1].clone()))
+                .collect())
+        };
+        let strings = |key: &str| -> Result<Vec<String>, MdParseError> {
+            Ok(arrays(key, 0)?.into_iter().flatten().collect())
+        };
+
+        // an empty delimiter would match everywhere
+        let delimiters = |key: &str, delimiters: Vec<&String>| {
+            if delimiters.iter().any(|d| d.is_empty()) {
+                Err(invalid(key, "delimiters that are not empty"))
+            } else {
+                Ok(())
+            }
+        };
+        let lines = pairs("lines")?;
+        delimiters("lines", lines.iter().map(|(_, prefix)| prefix).collect())?;
+        let regions: Vec<_> = arrays("regions", 3)?
+            .into_iter()
+            .map(|r| (r[0].clone(), r[1].clone(), r[2].clone()))
+            .collect();
+        delimiters(
+            "regions",
+            regions
+                .iter()
+                .flat_map(|(_, start, end)| [start, end])
+                .collect(),
+        )?;
+        let quotes = strings("strings")?;
+        delimiters("strings", quotes.iter().collect())?;
+        let char_quotes = strings("chars")?;
+        delimiters("chars", char_quotes.iter().collect())?;
+        let prefixes = pairs("prefixes")?;
+        delimiters(
+            "prefixes",
+            prefixes.iter().map(|(_, prefix)| prefix).collect(),
+        )?;
+
+        let mut words = HashMap::new();
+        for (key, class) in WORD_CLASSES {
+            for word in strings(key)? {
+                words.insert(word, class);
+            }
+        }
+        Ok(Self {
+            name: fields
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid("name", "a string"))?
+                .to_string(),
+            aliases: strings("aliases")?,
+            lines,
+            regions,
+            strings: quotes,
+            chars: char_quotes,
+            prefixes,
+            numbers: match fields.get("numbers") {
+                None => false,
+                Some(Value::Boolean(numbers)) => *numbers,
+                Some(_) => Err(invalid("numbers", "a boolean"))?,
+            },
+            word_chars: match fields.get("word_chars") {
+                None => Vec::new(),
+                Some(Value::String(chars)) => chars.chars().collect(),
+                Some(_) => Err(invalid("word_chars", "a string"))?,
+            },
+            words,
+        })
+    }
+
+    /// Renders the code as escaped HTML, with its tokens in `<span>`s of their class.
+    #[must_use]
+    pub fn highlight(&self, code: &str) -> String {
+        self.tokens(code)
+            .into_iter()
+            .map(|(class, text)| token_html(class, text))
+            .collect()
+    }
+
+    /// Splits the code into tokens with their class, and the plain text between them without.
+    #[must_use]
+    pub fn tokens<'c>(&self, code: &'c str) -> Vec<(Option<&str>, &'c str)> {
+        let mut tokens = Vec::new();
+        // start of the text that is not part of a token
+        let mut plain = 0;
+        let mut i = 0;
+        while let Some(c) = code[i..].chars().next() {
+            let Some((class, end)) = self.token(code, i).filter(|&(_, end)| end > i) else {
+                // empty tokens are plain text, so the code is read to the end
+                i += c.len_utf8();
+                continue;
+            };
+            if plain < i {
+                tokens.push((None, &code[plain..i]));
+            }
+            tokens.push((Some(class), &code[i..end]));
+            i = end;
+            plain = end;
+        }
+        if plain < code.len() {
+            tokens.push((None, &code[plain..]));
+        }
+        tokens
+    }
+
+    /// The class and the end of the token starting at `i`, if there is one.
+    fn token(&self, code: &str, i: usize) -> Option<(&str, usize)> {
+        let rest = &code[i..];
+        let line_end = |from: usize| code[from..].find('\n').map_or(code.len(), |n| from + n);
+
+        if i == 0 || code[..i].ends_with('\n') {
+            let content = rest.trim_start_matches([' ', '\t']);
+            if let Some((class, _)) = self.lines.iter().find(|(_, p)| content.starts_with(p)) {
+                return Some((class, line_end(i)));
+            }
+        }
+        for (class, start, end) in &self.regions {
+            if rest.starts_with(start) {
+                let from = i + start.len();
+                let end = match end.as_str() {
+                    "\n" => line_end(from),
+                    end => code[from..]
+                        .find(end)
+                        .map_or(code.len(), |n| from + n + end.len()),
+                };
+                return Some((class, end));
+            }
+        }
+        if let Some(quote) = self.strings.iter().find(|q| rest.starts_with(q.as_str())) {
+            return Some(("string", string_end(code, i + quote.len(), quote)));
+        }
+        // before prefixes, so `'x'` is no lifetime in Rust
+        if let Some(len) = self.chars.iter().find_map(|q| char_literal_len(rest, q)) {
+            return Some(("string", i + len));
+        }
+
+        // the rest only starts at the beginning of a word
+        if code[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| self.is_word_char(c))
+        {
+            return None;
+        }
+        if self.numbers && rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let mut end = i;
+            for (j, c) in rest.char_indices() {
+                let decimal_point =
+                    c == '.' && rest[j + 1..].starts_with(|d: char| d.is_ascii_digit());
+                if !(c.is_alphanumeric() || c == '_' || decimal_point) {
+                    break;
+                }
+                end = i + j + c.len_utf8();
+            }
+            return Some(("number", end));
+        }
+        if let Some((class, prefix)) = self.prefixes.iter().find(|(_, p)| rest.starts_with(p)) {
+            return Some((class, self.word_end(code, i + prefix.len())));
+        }
+        let end = self.word_end(code, i);
+        self.words.get(&code[i..end]).map(|&class| (class, end))
+    }
+
+    fn is_word_char(&self, c: char) -> bool {
+        c.is_alphanumeric() || c == '_' || self.word_chars.contains(&c)
+    }
+
+    fn word_end(&self, code: &str, from: usize) -> usize {
+        code[from..]
+            .find(|c| !self.is_word_char(c))
+            .map_or(code.len(), |n| from + n)
+    }
+}
+
+/// Renders a token as escaped HTML, in a `<span>` of its class if it has one.
+#[must_use]
+pub fn token_html(class: Option<&str>, text: &str) -> String {
+    match class {
+        Some(class) => format!(
+            "<span class=\"{}\">{}</span>",
+            escape_attr(class),
+            escape_text(text)
+        ),
+        None => escape_text(text),
+    }
+}
+
+/// Length of the character literal the code starts with: one character or a backslash escape
+/// like `\n` or `\u{1F600}` between quotes.
+fn char_literal_len(code: &str, quote: &str) -> Option<usize> {
+    let inner = code.strip_prefix(quote)?;
+    let content = if let Some(escape) = inner.strip_prefix('\\') {
+        let first = escape.chars().next()?;
+        let rest = &escape[first.len_utf8()..];
+        let close = rest.find(quote)?;
+        if rest[..close].contains(char::is_whitespace) {
+            return None;
+        }
+        1 + first.len_utf8() + close
+    } else {
+        inner.chars().next().filter(|&c| c != '\n')?.len_utf8()
+    };
+    inner[content..]
+        .starts_with(quote)
+        .then_some(2 * quote.len() + content)
+}
+
+/// End of a string whose content starts at `from`, after its closing quote or at the end.
+fn string_end(code: &str, from: usize, quote: &str) -> usize {
+    let mut chars = code[from..].char_indices();
+    while let Some((j, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if code[from + j..].starts_with(quote) {
+            return from + j + quote.len();
+        }
+    }
+    code.len()
+}
+
+/// Grammars by the names and aliases of their languages, case-insensitive.
+#[derive(Debug, Clone, Default)]
+pub struct Grammars {
+    grammars: Vec<Grammar>,
+    names: HashMap<String, usize>,
+}
+
+impl Grammars {
+    /// The grammars that come with gravel: Rust, shell, TOML, JSON, Python, HTML, CSS and
+    /// Markdown.
+    #[must_use]
+    pub fn builtin() -> &'static Self {
+        static BUILTIN_GRAMMARS: LazyLock<Grammars> = LazyLock::new(|| {
+            let mut grammars = Grammars::default();
+            for definition in BUILTIN {
+                grammars.add(Grammar::parse(definition).expect("built-in grammars are valid"));
+            }
+            grammars
+        });
+        &BUILTIN_GRAMMARS
+    }
+
+    /// The built-in grammars and the ones defined in the `.toml` files of the directory, which
+    /// take the place of built-in grammars with the same names.
+    ///
+    /// # Errors
+    /// A file can't be read or is not a valid definition.
+    pub fn load(dir: &PathBuf) -> Result<Self, Error> {
+        let mut grammars = Self::builtin().clone();
+        let mut files: Vec<_> = crawl_fs(dir)
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        files.sort();
+        for path in files {
+            let file = dir.join(&path);
+            let definition = fs::read_to_string(&file).map_err(|e| {
+                Error::FSError(format!("Reading grammar `{}` failed: {e}", file.display()))
+            })?;
+            let grammar = Grammar::parse(&definition)
+                .map_err(|e| e.set_file(file).set_source(&definition))?;
+            grammars.add(grammar);
+        }
+        Ok(grammars)
+    }
+
+    /// Adds a grammar, which takes over its names from grammars added before.
+    pub fn add(&mut self, grammar: Grammar) {
+        let index = self.grammars.len();
+        for name in std::iter::once(&grammar.name).chain(&grammar.aliases) {
+            self.names.insert(name.to_lowercase(), index);
+        }
+        self.grammars.push(grammar);
+    }
+
+    #[must_use]
+    pub fn get(&self, language: &str) -> Option<&Grammar> {
+        self.names
+            .get(&language.to_lowercase())
+            .map(|&index| &self.grammars[index])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Grammar, Grammars};
+
+    #[test]
+    fn builtin_languages() {
+        let grammars = Grammars::builtin();
+        for language in [
+            "rust", "rs", "shell", "bash", "toml", "json", "python", "py", "html", "css",
+            "markdown", "md", "Rust",
+        ] {
+            assert!(grammars.get(language).is_some(), "{language}");
+        }
+        assert!(grammars.get("cobol").is_none());
+    }
+
+    #[test]
+    fn rust_tokens() {
+        let rust = Grammars::builtin().get("rust").unwrap();
+        assert_eq!(
+            rust.highlight("fn main() { let x: u8 = 0xff; } // done\n"),
+            "<span class=\"keyword\">fn</span> main() { <span class=\"keyword\">let</span> x: \
+             <span class=\"type\">u8</span> = <span class=\"number\">0xff</span>; } \
+             <span class=\"comment\">// done</span>\n"
+        );
+        // character literals are no lifetimes
+        assert_eq!(
+            rust.highlight("fn f<'a>(c: &'a char) -> bool { *c == 'x' || *c == '\\'' }"),
+            "<span class=\"keyword\">fn</span> f&lt;<span class=\"lifetime\">'a</span>&gt;(c: \
+             &amp;<span class=\"lifetime\">'a</span> <span class=\"type\">char</span>) -&gt; \
+             <span class=\"type\">bool</span> { *c == <span class=\"string\">'x'</span> || \
+             *c == <span class=\"string\">'\\''</span> }"
+        );
+        // words are only matched whole, and strings are escaped
+        assert_eq!(
+            rust.highlight("format_fn(\"<\\\"a\\\">\")"),
+            "format_fn(<span class=\"string\">\"&lt;\\\"a\\\"&gt;\"</span>)"
+        );
+    }
+
+    #[test]
+    fn line_and_prefix_rules() {
+        let toml = Grammars::builtin().get("toml").unwrap();
+        assert_eq!(
+            toml.highlight("[server]\nport = 8080 # default\n"),
+            "<span class=\"section\">[server]</span>\nport = <span class=\"number\">8080</span> \
+             <span class=\"comment\"># default</span>\n"
+        );
+        let shell = Grammars::builtin().get("sh").unwrap();
+        assert_eq!(
+            shell.highlight("echo $HOME"),
+            "<span class=\"builtin\">echo</span> <span class=\"variable\">$HOME</span>"
+        );
+    }
+
+    #[test]
+    fn custom_grammar() {
+        let definition = "name = \"ini\"\naliases = [\"cfg\"]\nlines = [[\"section\", \"[\"]]\nregions = [[\"comment\", \";\", \"\\n\"]]";
+        let mut grammars = Grammars::default();
+        grammars.add(Grammar::parse(definition).unwrap());
+        assert_eq!(
+            grammars.get("cfg").unwrap().highlight("[a]\nb=1 ; c"),
+            "<span class=\"section\">[a]</span>\nb=1 <span class=\"comment\">; c</span>"
+        );
+    }
+
+    #[test]
+    fn invalid_grammar() {
+        let err = Grammar::parse("name = \"x\"\nregions = [[\"comment\", \"#\"]]").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("on line 2: expected 'an array of arrays of 3 strings for `regions`'")
+        );
+        assert!(Grammar::parse("aliases = []").is_err());
+    }
+
+    #[test]
+    fn empty_delimiters() {
+        let err = Grammar::parse("name = \"x\"\nlines = [[\"comment\", \"\"]]").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("on line 2: expected 'delimiters that are not empty for `lines`'")
+        );
+        for definition in [
+            "name = \"x\"\nregions = [[\"comment\", \"\", \"\\n\"]]",
+            "name = \"x\"\nstrings = [\"\"]",
+            "name = \"x\"\nchars = [\"\"]",
+            "name = \"x\"\nprefixes = [[\"variable\", \"\"]]",
+        ] {
+            assert!(Grammar::parse(definition).is_err(), "{definition}");
+        }
+
+        // a grammar with an empty line prefix highlights empty lines as empty tokens
+        let grammar = Grammar {
+            lines: vec![("comment".to_string(), String::new())],
+            ..Grammar::default()
+        };
+        assert_eq!(
+            grammar.highlight("a\n\nb"),
+            "<span class=\"comment\">a</span>\n\n<span class=\"comment\">b</span>"
+        );
+    }
+}
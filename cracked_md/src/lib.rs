@@ -4,28 +4,41 @@
 #![deny(dead_code, unused_imports)]
 #![allow(clippy::needless_pass_by_value)]
 
+use ast::{Document, Metadata, Position};
 use fstools::crawl_fs;
-use parser::parse;
+use highlight::Grammars;
+use manifest::{Entry, Manifest};
+use parser::{Dialect, Extensions, ParseOptions, parse_with};
 use slogger::{Level, log};
 use std::{
+    collections::BTreeMap,
     fmt::Display,
     fs::{self, File},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
     time::Instant,
 };
+use template::{TemplateError, TemplateValue, Templates};
 use to_html::ToHtml;
 
 pub mod ast;
+pub mod highlight;
+mod links;
+mod manifest;
 mod parse_trait;
 pub mod parser;
+pub mod template;
 pub mod to_html;
 
 #[derive(Debug)]
 pub struct MdParseError {
     file: Option<PathBuf>,
     line: Option<usize>,
-    //col: Option<usize>,
+    col: Option<usize>,
+    /// The offending line of the source, shown under the message.
+    source_line: Option<Box<str>>,
     expected: String,
     got: String,
 }
@@ -35,7 +48,8 @@ impl MdParseError {
         Self {
             file: None,
             line: None,
-            //col: None,
+            col: None,
+            source_line: None,
             expected: expected.to_string(),
             got: got.to_string(),
         }
@@ -43,34 +57,35 @@ impl MdParseError {
 
     pub fn from_line(line: usize, expected: impl ToString, got: impl ToString) -> Self {
         Self {
-            file: None,
             line: Some(line),
-            //col: None,
-            expected: expected.to_string(),
-            got: got.to_string(),
+            ..Self::new(expected, got)
         }
     }
 
-    /*
-    pub fn from_col(col: usize, expected: impl ToString, got: impl ToString) -> Self {
+    #[must_use]
+    pub fn set_line(self, line: usize) -> Self {
+        Self {
+            line: Some(line),
+            ..self
+        }
+    }
+
+    /// Sets the column, counted in characters from 1.
+    #[must_use]
+    pub fn set_col(self, col: usize) -> Self {
         Self {
-            file: None,
-            line: None,
             col: Some(col),
-            expected: expected.to_string(),
-            got: got.to_string(),
+            ..self
         }
     }
-    */
 
+    /// Sets the line and column of a position in the source.
     #[must_use]
-    pub fn set_line(self, line: usize) -> Self {
+    pub fn at(self, position: Position) -> Self {
         Self {
-            file: self.file,
-            line: Some(line),
-            //col: self.col,
-            expected: self.expected,
-            got: self.got,
+            line: Some(position.line),
+            col: Some(position.col),
+            ..self
         }
     }
 
@@ -78,27 +93,62 @@ impl MdParseError {
     pub fn set_file(self, file: PathBuf) -> Self {
         Self {
             file: Some(file),
-            line: self.line,
-            //col: self.col,
-            expected: self.expected,
-            got: self.got,
+            ..self
+        }
+    }
+
+    /// Keeps the line of `source` the error is on, to be shown with the message.
+    #[must_use]
+    pub fn set_source(self, source: &str) -> Self {
+        let source_line = self
+            .line
+            .and_then(|line| source.lines().nth(line.checked_sub(1)?))
+            .map(Box::from);
+        Self {
+            source_line,
+            ..self
         }
     }
 }
 
 impl Display for MdParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // no error message :/
         let file = self.file.clone().unwrap_or("<unknown>".into());
         write!(
             f,
-            "Parse error in '{}' on line {}: expected '{}', got '{}'",
+            "Parse error in '{}' on line {}",
             file.display(),
-            self.line.unwrap_or(0),
-            //self.col.unwrap_or(0),
-            self.expected,
-            self.got
-        )
+            self.line.unwrap_or(0)
+        )?;
+        if let Some(col) = self.col {
+            write!(f, ", column {col}")?;
+        }
+        write!(f, ": expected '{}', got '{}'", self.expected, self.got)?;
+
+        // the source line with a caret under the column, or the whole line underlined
+        if let (Some(source), Some(line)) = (&self.source_line, self.line) {
+            let gutter = " ".repeat(line.to_string().len());
+            let (start, len) = match self.col {
+                Some(col) => (col.saturating_sub(1), 1),
+                None => {
+                    let indent = source.chars().take_while(|c| c.is_whitespace()).count();
+                    (indent, source.trim().chars().count().max(1))
+                }
+            };
+            // keep tabs so the marker lines up with the source
+            let pad: String = source
+                .chars()
+                .chain(std::iter::repeat(' '))
+                .take(start)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            write!(
+                f,
+                "\n {line} | {source}\n {gutter} | {pad}{}",
+                "^".repeat(len)
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -108,11 +158,24 @@ impl std::error::Error for MdParseError {}
 pub enum Error {
     FSError(String),
     Parse(MdParseError),
+    Template(TemplateError),
+    /// Errors of all the files that failed, when a build keeps going past the first one.
+    Multiple(Vec<Error>),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", &self)
+        match self {
+            Error::FSError(s) => write!(f, "{s}"),
+            Error::Parse(e) => e.fmt(f),
+            Error::Template(e) => e.fmt(f),
+            Error::Multiple(errors) => {
+                for e in errors {
+                    writeln!(f, "{e}\n")?;
+                }
+                write!(f, "{} files failed to build", errors.len())
+            }
+        }
     }
 }
 
@@ -122,18 +185,95 @@ impl From<MdParseError> for Error {
     }
 }
 
+impl From<TemplateError> for Error {
+    fn from(value: TemplateError) -> Self {
+        Error::Template(value)
+    }
+}
+
 impl std::error::Error for Error {}
 
 type Result<T> = std::result::Result<T, crate::Error>;
 
-/// Takes two directories and a force flag as parameters, generates html files to the outdir in the
-/// same directory structure as the md files in indir.
+/// Settings of [`generate`] besides the directories.
+#[derive(Debug)]
+pub struct GenerateOptions {
+    /// Overwrite existing files in the out directory.
+    pub force: bool,
+    /// Rebuild everything, instead of only the files changed since the last build.
+    pub full: bool,
+    /// Extensions (without the dot, case-insensitive) of the Markdown files to turn into HTML.
+    /// All other files are copied as they are.
+    pub markdown_extensions: Vec<String>,
+    /// Directory of layouts and the partials they include. A page uses the layout named in its
+    /// `layout` metadata, or `default.html`. Without a layout pages get a bare HTML document.
+    pub templates: Option<PathBuf>,
+    /// Directory of grammar definitions (`*.toml`) for highlighting code blocks, besides the
+    /// built-in ones. See [`highlight`] for the format.
+    pub grammars: Option<PathBuf>,
+    /// Site-wide variables, available to templates as `site`.
+    pub site: Metadata,
+    /// Number of files built at the same time, by default one per CPU core. The output is the
+    /// same for any number of jobs.
+    pub jobs: usize,
+    /// Build every file even if some fail, and report the errors of all of them at once.
+    pub keep_going: bool,
+    /// Fail on problems in Markdown files, instead of reading them leniently with a warning.
+    pub strict: bool,
+    /// The flavor of Markdown the files are written in.
+    pub dialect: Dialect,
+    /// Inline syntax on top of the dialect, like `~~strikethrough~~`.
+    pub syntax_extensions: Extensions,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            force: false,
+            full: false,
+            markdown_extensions: vec!["md".to_string(), "markdown".to_string()],
+            templates: None,
+            grammars: None,
+            site: Metadata::default(),
+            jobs: thread::available_parallelism().map_or(1, std::num::NonZero::get),
+            keep_going: false,
+            strict: false,
+            dialect: Dialect::default(),
+            syntax_extensions: Extensions::default(),
+        }
+    }
+}
+
+impl GenerateOptions {
+    fn is_markdown(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| {
+            self.markdown_extensions
+                .iter()
+                .any(|md| ext.eq_ignore_ascii_case(md))
+        })
+    }
+}
+
+/// Takes two directories and options as parameters, generates html files to the outdir in the
+/// same directory structure as the md files in indir. Other files in indir are copied to the
+/// same relative paths, so pages can link to the images and styles next to them. Relative links
+/// to Markdown files are rewritten to the pages generated from them, and links to missing pages
+/// are reported as warnings.
+///
+/// A manifest of the build is kept in outdir. Files unchanged since the last build are skipped,
+/// unless the templates or options changed, and outputs of deleted sources are removed.
 ///
 /// # Errors
-/// Anything wrong with reading files from the directories or parsing the files.
-pub fn generate(indir: &PathBuf, outdir: &PathBuf, force: bool) -> Result<()> {
+/// Anything wrong with reading files from the directories, parsing the files or rendering the
+/// templates.
+/// With [`GenerateOptions::keep_going`] every file is still built, and the errors of all the
+/// files that failed are returned together as [`Error::Multiple`].
+pub fn generate(indir: &PathBuf, outdir: &Path, options: &GenerateOptions) -> Result<()> {
     let start_time = Instant::now();
     let mut generated_files = 0;
+    let mut copied_files = 0;
+    let mut skipped_files = 0;
+    let mut removed_files = 0;
 
     if !indir.is_dir() {
         Err(Error::FSError("In directory not found".to_string()))?;
@@ -141,65 +281,149 @@ pub fn generate(indir: &PathBuf, outdir: &PathBuf, force: bool) -> Result<()> {
     if !outdir.is_dir() {
         Err(Error::FSError("Out directory not found".to_string()))?;
     }
-    let files = crawl_fs(indir);
-
-    for path in files {
-        let fullpath = indir.as_path().join(&path);
-
-        // read and parse md file
-        let content = fs::read_to_string(&fullpath)
-            .map_err(|_e| Error::FSError(format!("File `{}` read error", path.display())))?;
-        let html = parse(&content)?.to_html();
-
-        // write html data to file
-        let mut newpath = outdir.to_owned();
-        newpath.push(&path);
-        newpath.set_extension("html");
-
-        // check if path exists
-        if newpath.exists() {
-            // remove if is file and if force, otherwise error
-            if newpath.is_file() {
-                if force {
-                    fs::remove_file(&newpath).map_err(|_e| {
-                        Error::FSError(format!("File `{}` deleting not allowed", newpath.display()))
-                    })?;
-                } else {
-                    Err(Error::FSError(
-                        "File overwrite denied, enable force overwrite".to_string(),
-                    ))?;
+    let templates = match &options.templates {
+        Some(dir) if dir.is_dir() => Some(Templates::load(dir)?),
+        Some(dir) => Err(Error::FSError(format!(
+            "Templates directory `{}` not found",
+            dir.display()
+        )))?,
+        None => None,
+    };
+    let custom_grammars = match &options.grammars {
+        Some(dir) if dir.is_dir() => Some(Grammars::load(dir)?),
+        Some(dir) => Err(Error::FSError(format!(
+            "Grammars directory `{}` not found",
+            dir.display()
+        )))?,
+        None => None,
+    };
+    let grammars = custom_grammars.as_ref().unwrap_or(Grammars::builtin());
+
+    let mut old_manifest = Manifest::load(outdir).unwrap_or_default();
+    if options.full {
+        // clean rebuild, nothing from the last build is kept
+        for entry in std::mem::take(&mut old_manifest.entries).values() {
+            remove_out_file(&outdir.join(&entry.output))?;
+        }
+    }
+    let mut manifest = Manifest {
+        layouts: options
+            .templates
+            .as_ref()
+            .map_or(0, manifest::dir_fingerprint),
+        config: manifest::hash(
+            format!(
+                "{} {:?} {} {:?} {:?} {:?} {}",
+                env!("CARGO_PKG_VERSION"),
+                options.markdown_extensions,
+                options.strict,
+                options.dialect,
+                options.syntax_extensions,
+                options.site,
+                options
+                    .grammars
+                    .as_ref()
+                    .map_or(0, manifest::dir_fingerprint)
+            )
+            .as_bytes(),
+        ),
+        entries: BTreeMap::new(),
+    };
+    let config_changed =
+        manifest.layouts != old_manifest.layouts || manifest.config != old_manifest.config;
+
+    let mut files: Vec<_> = crawl_fs(indir).into_iter().collect();
+    files.sort();
+
+    let context = BuildContext {
+        indir,
+        outdir,
+        options,
+        templates: templates.as_ref(),
+        grammars,
+        sources: &files,
+        old_manifest: &old_manifest,
+        config_changed,
+    };
+    // files are built independently, so they are spread over a pool of workers taking the next
+    // file in order; results are gathered back in the same order, for the first error and the
+    // manifest to not depend on scheduling
+    let next = AtomicUsize::new(0);
+    let workers = options.jobs.clamp(1, files.len().max(1));
+    let mut results: Vec<(usize, Result<(Built, Entry)>)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = files.get(i) else { break };
+                        results.push((i, build_file(path, &context)));
+                    }
+                    results
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut errors = Vec::new();
+    for (path, (_, result)) in files.into_iter().zip(results) {
+        let (built, entry) = match result {
+            Ok(built) => built,
+            Err(e) => {
+                // the output of the last build stays, but is rebuilt next time
+                if let Some(old) = old_manifest.entries.get(&path) {
+                    let stale = Entry {
+                        mtime: 0,
+                        hash: 0,
+                        output: old.output.clone(),
+                        links: Vec::new(),
+                    };
+                    manifest.entries.insert(path, stale);
                 }
-            } else {
-                Err(Error::FSError(format!(
-                    "Directory `{}` in place of file in out directory",
-                    newpath.display()
-                )))?;
+                errors.push(e);
+                continue;
             }
+        };
+        match built {
+            Built::Generated => generated_files += 1,
+            Built::Copied => copied_files += 1,
+            Built::Skipped => skipped_files += 1,
         }
+        manifest.entries.insert(path, entry);
+    }
 
-        //println!("About to write file '{}'", newpath.display());
-
-        let parent = newpath.parent().ok_or(Error::FSError(format!(
-            "Access to parent directory of `{}` denied",
-            newpath.display()
-        )))?;
-        fs::create_dir_all(parent)
-            .map_err(|_e| Error::FSError("Creating directory tree failed".to_string()))?;
-        let mut newfile = File::create_new(&newpath).map_err(|_e| {
-            Error::FSError(format!("Creating file `{}` failed", &newpath.display()))
-        })?;
+    // remove outputs of deleted sources, unless another source took their place
+    for (source, entry) in &old_manifest.entries {
+        let replaced = manifest.entries.values().any(|e| e.output == entry.output);
+        if !manifest.entries.contains_key(source) && !replaced {
+            remove_out_file(&outdir.join(&entry.output))?;
+            log!(
+                Level::Debug,
+                "File `{}` removed, its source `{}` is gone",
+                entry.output.display(),
+                source.display()
+            );
+            removed_files += 1;
+        }
+    }
 
-        newfile.write(html.as_bytes()).map_err(|_e| {
-            Error::FSError(format!("Writing to file `{}` failed", newpath.display()))
-        })?;
+    manifest
+        .save(outdir)
+        .map_err(|_e| Error::FSError("Writing the build manifest failed".to_string()))?;
 
-        log!(
-            Level::Debug,
-            "File `{}` generation to `{}` successful",
-            path.display(),
-            newpath.display()
-        );
-        generated_files += 1;
+    // only reported once the manifest is saved, so that the pages built anyway are not denied
+    // overwriting next time
+    if options.keep_going && !errors.is_empty() {
+        return Err(Error::Multiple(errors));
+    }
+    if !errors.is_empty() {
+        return Err(errors.remove(0));
     }
 
     let time = start_time.elapsed();
@@ -212,10 +436,541 @@ pub fn generate(indir: &PathBuf, outdir: &PathBuf, force: bool) -> Result<()> {
 
     log!(
         Level::Info,
-        "Generated {} files in {} without reported errors",
+        "Generated {} files and copied {} files ({} rebuilt, {} skipped, {} removed) in {} without reported errors",
         generated_files,
+        copied_files,
+        generated_files + copied_files,
+        skipped_files,
+        removed_files,
         time_report
     );
 
     Ok(())
 }
+
+/// What [`generate`] needs to know to build a single file.
+struct BuildContext<'a> {
+    indir: &'a Path,
+    outdir: &'a Path,
+    options: &'a GenerateOptions,
+    templates: Option<&'a Templates>,
+    grammars: &'a Grammars,
+    /// Sorted source paths, relative to the in directory.
+    sources: &'a [PathBuf],
+    old_manifest: &'a Manifest,
+    config_changed: bool,
+}
+
+/// What happened to a source file during a build.
+enum Built {
+    Generated,
+    Copied,
+    Skipped,
+}
+
+/// Generates or copies one file of the in directory, unless it is unchanged since the last
+/// build. Returns the manifest entry of the file.
+fn build_file(path: &Path, context: &BuildContext) -> Result<(Built, Entry)> {
+    let fullpath = context.indir.join(path);
+    let is_markdown = context.options.is_markdown(path);
+    let output = if is_markdown {
+        path.with_extension("html")
+    } else {
+        path.to_path_buf()
+    };
+    let newpath = context.outdir.join(&output);
+    let mtime = manifest::mtime(&fullpath);
+
+    // skip sources that are unchanged since the last build, checking the content only if
+    // the modification time changed; pages linking to missing pages are rebuilt to report them
+    let old_entry = context.old_manifest.entries.get(path).filter(|e| {
+        e.output == output
+            && newpath.is_file()
+            && !(is_markdown && context.config_changed)
+            && e.links
+                .iter()
+                .all(|l| context.sources.binary_search(l).is_ok())
+    });
+    if let Some(entry) = old_entry
+        && entry.mtime == mtime
+    {
+        return Ok((Built::Skipped, entry.clone()));
+    }
+    let content = fs::read(&fullpath)
+        .map_err(|_e| Error::FSError(format!("File `{}` read error", path.display())))?;
+    let hash = manifest::hash(&content);
+    let mut entry = Entry {
+        mtime,
+        hash,
+        output,
+        links: old_entry.map(|e| e.links.clone()).unwrap_or_default(),
+    };
+    if old_entry.is_some_and(|e| e.hash == hash) {
+        return Ok((Built::Skipped, entry));
+    }
+
+    // files of the last build are ours to overwrite
+    let force = context.options.force || context.old_manifest.entries.contains_key(path);
+
+    // anything but markdown is copied as is
+    if !is_markdown {
+        prepare_out_file(&newpath, force)?;
+        fs::write(&newpath, &content).map_err(|_e| {
+            Error::FSError(format!(
+                "Copying file `{}` to `{}` failed",
+                path.display(),
+                newpath.display()
+            ))
+        })?;
+        log!(
+            Level::Debug,
+            "File `{}` copied to `{}`",
+            path.display(),
+            newpath.display()
+        );
+        return Ok((Built::Copied, entry));
+    }
+
+    // parse md file
+    let content = String::from_utf8(content)
+        .map_err(|_e| Error::FSError(format!("File `{}` read error", path.display())))?;
+    let parse_options = ParseOptions {
+        strict: context.options.strict,
+        dialect: context.options.dialect,
+        extensions: context.options.syntax_extensions,
+    };
+    let parsed = parse_with(&content, &parse_options)
+        .map_err(|e| e.set_file(fullpath.clone()).set_source(&content))?;
+    for warning in parsed.warnings {
+        log!(
+            Level::Warn,
+            "{}",
+            warning.set_file(fullpath.clone()).set_source(&content)
+        );
+    }
+    let mut doc = parsed.document;
+    let (links, broken) = links::rewrite_links(
+        &mut doc.blocks,
+        &parsed.spans,
+        path,
+        context.sources,
+        context.options,
+    );
+    for warning in broken {
+        log!(
+            Level::Warn,
+            "{}",
+            warning.set_file(fullpath.clone()).set_source(&content)
+        );
+    }
+    entry.links = links;
+    let html = render_page(doc, path, context)?;
+
+    // write html data to file
+    prepare_out_file(&newpath, force)?;
+
+    let mut newfile = File::create_new(&newpath)
+        .map_err(|_e| Error::FSError(format!("Creating file `{}` failed", newpath.display())))?;
+
+    newfile
+        .write(html.as_bytes())
+        .map_err(|_e| Error::FSError(format!("Writing to file `{}` failed", newpath.display())))?;
+
+    log!(
+        Level::Debug,
+        "File `{}` generation to `{}` successful",
+        path.display(),
+        newpath.display()
+    );
+    Ok((Built::Generated, entry))
+}
+
+/// Removes a file from the out directory if it exists.
+fn remove_out_file(path: &Path) -> Result<()> {
+    if path.is_file() {
+        fs::remove_file(path).map_err(|_e| {
+            Error::FSError(format!("File `{}` deleting not allowed", path.display()))
+        })?;
+    }
+    Ok(())
+}
+
+/// Makes way for a file in the out directory: an existing file is removed if `force` is set,
+/// missing parent directories are created.
+fn prepare_out_file(newpath: &Path, force: bool) -> Result<()> {
+    // check if path exists
+    if newpath.exists() {
+        // remove if is file and if force, otherwise error
+        if newpath.is_file() {
+            if force {
+                fs::remove_file(newpath).map_err(|_e| {
+                    Error::FSError(format!("File `{}` deleting not allowed", newpath.display()))
+                })?;
+            } else {
+                Err(Error::FSError(
+                    "File overwrite denied, enable force overwrite".to_string(),
+                ))?;
+            }
+        } else {
+            Err(Error::FSError(format!(
+                "Directory `{}` in place of file in out directory",
+                newpath.display()
+            )))?;
+        }
+    }
+
+    let parent = newpath.parent().ok_or(Error::FSError(format!(
+        "Access to parent directory of `{}` denied",
+        newpath.display()
+    )))?;
+    fs::create_dir_all(parent)
+        .map_err(|_e| Error::FSError("Creating directory tree failed".to_string()))?;
+    Ok(())
+}
+
+/// Wraps the page in its layout, with the variables `content`, `page` (the metadata) and `site`.
+fn render_page(doc: Document, path: &Path, context: &BuildContext) -> Result<String> {
+    let layout = doc.metadata.get("layout").and_then(|l| l.as_str());
+    let name = format!("{}.html", layout.unwrap_or("default"));
+    let Some(templates) = context.templates.filter(|t| t.contains(&name)) else {
+        if layout.is_some() {
+            Err(Error::FSError(format!(
+                "Layout `{}` of page `{}` not found",
+                name,
+                path.display()
+            )))?;
+        }
+        return Ok(doc.to_html_with(context.grammars));
+    };
+
+    let variables = BTreeMap::from([
+        ("page".to_string(), TemplateValue::from(&doc.metadata)),
+        (
+            "site".to_string(),
+            TemplateValue::from(&context.options.site),
+        ),
+        (
+            "content".to_string(),
+            TemplateValue::Html(doc.blocks.to_html_with(context.grammars)),
+        ),
+    ]);
+    Ok(templates.render(&name, &variables)?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use crate::{Error, GenerateOptions, MdParseError, generate, parser::parse};
+
+    /// A fresh directory with `in` and `out` subdirectories, the files are written under `in`.
+    fn site_dir(name: &str, files: &[(&str, &[u8])]) -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("cracked_md-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (indir, outdir) = (root.join("in"), root.join("out"));
+        fs::create_dir_all(&outdir).unwrap();
+        for (path, content) in files {
+            let path = indir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        (indir, outdir)
+    }
+
+    #[test]
+    fn markdown_extensions() {
+        let options = GenerateOptions::default();
+        assert!(options.is_markdown("a/b.md".as_ref()));
+        assert!(options.is_markdown("README.MD".as_ref()));
+        assert!(!options.is_markdown("notes.txt".as_ref()));
+        assert!(!options.is_markdown("md".as_ref()));
+    }
+
+    #[test]
+    fn assets_are_copied_verbatim() {
+        let png: &[u8] = &[0x89, b'P', b'N', b'G', 0, 0xff];
+        let (indir, outdir) = site_dir(
+            "assets",
+            &[
+                ("guide/page.md", b"# Page\n\n![shot](shot.png)"),
+                ("guide/shot.png", png),
+                ("notes.txt", b"*not markdown*"),
+            ],
+        );
+
+        generate(&indir, &outdir, &GenerateOptions::default()).unwrap();
+
+        assert_eq!(fs::read(outdir.join("guide/shot.png")).unwrap(), png);
+        assert_eq!(
+            fs::read_to_string(outdir.join("notes.txt")).unwrap(),
+            "*not markdown*"
+        );
+        assert!(!outdir.join("notes.html").exists());
+        assert!(
+            fs::read_to_string(outdir.join("guide/page.html"))
+                .unwrap()
+                .contains("<img src=\"shot.png\" alt=\"shot\">")
+        );
+    }
+
+    #[test]
+    fn custom_grammars() {
+        let (indir, outdir) = site_dir(
+            "grammars",
+            &[(
+                "page.md",
+                b"```ini\n[server]\n```\n\n```rs\nfn main() {}\n```",
+            )],
+        );
+        let grammars = outdir.with_file_name("grammars");
+        fs::create_dir_all(&grammars).unwrap();
+        fs::write(
+            grammars.join("ini.toml"),
+            "name = \"ini\"\nlines = [[\"section\", \"[\"]]",
+        )
+        .unwrap();
+        let options = GenerateOptions {
+            grammars: Some(grammars.clone()),
+            ..Default::default()
+        };
+
+        generate(&indir, &outdir, &options).unwrap();
+        let html = fs::read_to_string(outdir.join("page.html")).unwrap();
+        assert!(html.contains(
+            "<code class=\"language-ini\"><span class=\"section\">[server]</span>\n</code>"
+        ));
+        assert!(html.contains("<span class=\"keyword\">fn</span> main"));
+
+        // a broken definition fails the build with its file
+        fs::write(grammars.join("bad.toml"), "name = 1").unwrap();
+        let err = generate(&indir, &outdir, &options).unwrap_err();
+        assert!(matches!(&err, Error::Parse(_)));
+        assert!(err.to_string().contains("bad.toml' on line 1"));
+    }
+
+    #[test]
+    fn incremental_rebuild() {
+        let (indir, outdir) = site_dir(
+            "incremental",
+            &[("a.md", b"# A"), ("b.md", b"# B"), ("style.css", b"p {}")],
+        );
+        let options = GenerateOptions::default();
+        generate(&indir, &outdir, &options).unwrap();
+
+        // unchanged files aren't rewritten, deleted sources lose their outputs
+        fs::write(outdir.join("b.html"), "stale").unwrap();
+        fs::write(indir.join("a.md"), "# A changed").unwrap();
+        fs::remove_file(indir.join("style.css")).unwrap();
+        generate(&indir, &outdir, &options).unwrap();
+
+        assert!(
+            fs::read_to_string(outdir.join("a.html"))
+                .unwrap()
+                .contains("A changed")
+        );
+        assert_eq!(fs::read_to_string(outdir.join("b.html")).unwrap(), "stale");
+        assert!(!outdir.join("style.css").exists());
+
+        // a full build rewrites everything
+        let full = GenerateOptions {
+            full: true,
+            ..GenerateOptions::default()
+        };
+        generate(&indir, &outdir, &full).unwrap();
+        assert!(
+            fs::read_to_string(outdir.join("b.html"))
+                .unwrap()
+                .contains("<h1 id=\"b\">B</h1>")
+        );
+    }
+
+    #[test]
+    fn links_between_pages() {
+        let (indir, outdir) = site_dir(
+            "links",
+            &[
+                ("index.md", b"[setup](guide/setup.md#install)"),
+                ("guide/setup.md", b"[home](../index.md) [next](next.md)"),
+            ],
+        );
+        let options = GenerateOptions::default();
+        generate(&indir, &outdir, &options).unwrap();
+
+        let html = |page| fs::read_to_string(outdir.join(page)).unwrap();
+        assert!(html("index.html").contains("<a href=\"guide/setup.html#install\">setup</a>"));
+        assert!(html("guide/setup.html").contains("<a href=\"../index.html\">home</a>"));
+        assert!(html("guide/setup.html").contains("<a href=\"next.html\">next</a>"));
+
+        // pages linking to missing pages are rebuilt until the target exists
+        fs::write(outdir.join("guide/setup.html"), "stale").unwrap();
+        fs::write(outdir.join("index.html"), "stale").unwrap();
+        generate(&indir, &outdir, &options).unwrap();
+        assert!(html("guide/setup.html").contains("next.html"));
+        assert_eq!(html("index.html"), "stale");
+
+        fs::write(indir.join("guide/next.md"), "# Next").unwrap();
+        generate(&indir, &outdir, &options).unwrap();
+        fs::write(outdir.join("guide/setup.html"), "stale").unwrap();
+        generate(&indir, &outdir, &options).unwrap();
+        assert_eq!(html("guide/setup.html"), "stale");
+    }
+
+    #[test]
+    fn parallel_build_matches_serial() {
+        let pages: Vec<_> = (0..40)
+            .map(|i| {
+                (
+                    format!("dir{}/page{i}.md", i % 3),
+                    format!("# Page {i}\n\ntext *{i}*"),
+                )
+            })
+            .collect();
+        let files: Vec<_> = pages
+            .iter()
+            .map(|(path, content)| (path.as_str(), content.as_bytes()))
+            .collect();
+        let (indir, serial) = site_dir("parallel", &files);
+        let parallel = serial.with_file_name("out-parallel");
+        fs::create_dir_all(&parallel).unwrap();
+
+        let jobs = |jobs| GenerateOptions {
+            jobs,
+            strict: true,
+            ..GenerateOptions::default()
+        };
+        generate(&indir, &serial, &jobs(1)).unwrap();
+        generate(&indir, &parallel, &jobs(8)).unwrap();
+        for (path, _) in &pages {
+            let path = PathBuf::from(path).with_extension("html");
+            assert_eq!(
+                fs::read(serial.join(&path)).unwrap(),
+                fs::read(parallel.join(&path)).unwrap()
+            );
+        }
+
+        // the reported error is the one of the first broken file, whoever finds it first
+        fs::write(indir.join("dir1/page4.md"), "#broken").unwrap();
+        fs::write(indir.join("dir2/page5.md"), "#broken").unwrap();
+        let serial_error = generate(&indir, &serial, &jobs(1)).unwrap_err();
+        let parallel_error = generate(&indir, &parallel, &jobs(8)).unwrap_err();
+        assert_eq!(serial_error.to_string(), parallel_error.to_string());
+        assert!(serial_error.to_string().contains("page4.md"));
+    }
+
+    #[test]
+    fn error_points_at_source() {
+        let source = "# Title\n\n##Broken";
+        let err = parse(source).unwrap_err().set_source(source);
+        assert_eq!(
+            err.to_string(),
+            "Parse error in '<unknown>' on line 3, column 3: expected '<space> after #', got 'no <space>'\n 3 | ##Broken\n   |   ^"
+        );
+
+        // unclosed delimiters are reported where they open
+        let source = "text\n  a `b";
+        let err = parse(source).unwrap_err().set_source(source);
+        assert!(err.to_string().ends_with("\n 2 |   a `b\n   |     ^"));
+
+        // front matter errors point at the key or value
+        let source = "+++\n  title \n+++";
+        let err = parse(source).unwrap_err().set_source(source);
+        assert!(err.to_string().ends_with("\n 2 |   title \n   |   ^"));
+        let source = "---\ntags: [a, 'b]\n---";
+        let err = parse(source).unwrap_err().set_source(source);
+        assert!(err.to_string().contains("on line 2, column 7"));
+
+        // without a column the whole line is marked
+        let err = MdParseError::from_line(2, "a", "b").set_source(source);
+        assert!(
+            err.to_string()
+                .ends_with("\n 2 | tags: [a, 'b]\n   | ^^^^^^^^^^^^^")
+        );
+    }
+
+    #[test]
+    fn keep_going_reports_every_broken_file() {
+        let (indir, outdir) = site_dir(
+            "keep-going",
+            &[
+                ("a.md", b"#a"),
+                ("b.md", b"# B"),
+                ("c/d.md", b"text\n\n```rust\nfn main() {}"),
+            ],
+        );
+        let options = GenerateOptions {
+            keep_going: true,
+            strict: true,
+            ..GenerateOptions::default()
+        };
+
+        let Err(Error::Multiple(errors)) = generate(&indir, &outdir, &options) else {
+            panic!("expected the errors of both broken files");
+        };
+        assert_eq!(errors.len(), 2);
+        let message = errors[0].to_string();
+        assert!(message.contains("a.md' on line 1, column 2"));
+        assert!(message.ends_with(" 1 | #a\n   |  ^"));
+        assert!(errors[1].to_string().contains("d.md' on line 3"));
+        // the good pages are still built
+        assert!(outdir.join("b.html").is_file());
+    }
+
+    #[test]
+    fn rebuild_after_failure() {
+        let (indir, outdir) = site_dir(
+            "rebuild-after-failure",
+            &[("a.md", b"#a"), ("b.md", b"# B"), ("c.md", b"# C")],
+        );
+        let strict = GenerateOptions {
+            strict: true,
+            jobs: 1,
+            ..GenerateOptions::default()
+        };
+        let lenient = GenerateOptions {
+            jobs: 1,
+            ..GenerateOptions::default()
+        };
+
+        // the pages built before and after the broken one are recorded in the manifest
+        assert!(generate(&indir, &outdir, &strict).is_err());
+        assert!(outdir.join("b.html").is_file());
+        generate(&indir, &outdir, &lenient).unwrap();
+        assert!(outdir.join("a.html").is_file());
+
+        fs::write(indir.join("b.md"), "# B, again").unwrap();
+        fs::write(indir.join("a.md"), "#a again").unwrap();
+        let keep_going = GenerateOptions {
+            keep_going: true,
+            strict: true,
+            jobs: 1,
+            ..GenerateOptions::default()
+        };
+        assert!(generate(&indir, &outdir, &keep_going).is_err());
+        fs::write(indir.join("a.md"), "# A").unwrap();
+        generate(&indir, &outdir, &strict).unwrap();
+        assert!(
+            fs::read_to_string(outdir.join("b.html"))
+                .unwrap()
+                .contains("B, again")
+        );
+    }
+
+    #[test]
+    fn lenient_by_default() {
+        let (indir, outdir) = site_dir("lenient", &[("a.md", b"5 * 3 = 15")]);
+
+        generate(&indir, &outdir, &GenerateOptions::default()).unwrap();
+        assert!(
+            fs::read_to_string(outdir.join("a.html"))
+                .unwrap()
+                .contains("<p>5 * 3 = 15</p>")
+        );
+
+        // the strict lint fails the same page
+        let strict = GenerateOptions {
+            strict: true,
+            ..GenerateOptions::default()
+        };
+        assert!(generate(&indir, &outdir, &strict).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Links between the pages of a site. Relative links to Markdown sources, like
+//! `[setup](../guide/setup.md#install)`, are rewritten to the HTML pages generated from them.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::{
+    GenerateOptions, MdParseError,
+    ast::{Block, Inline, SpanTree},
+};
+
+/// Rewrites the links of a page, see [`rewrite_links`].
+struct Rewriter<'a> {
+    /// Source path of the page, relative to the in directory.
+    page: &'a Path,
+    /// Sorted source paths of the site.
+    sources: &'a [PathBuf],
+    options: &'a GenerateOptions,
+    /// Source paths of the linked pages.
+    targets: Vec<PathBuf>,
+    /// Links to pages that don't exist.
+    broken: Vec<MdParseError>,
+}
+
+/// Rewrites the relative links to Markdown sources in the blocks of a page to the HTML generated
+/// from them, keeping the query and `#fragment`. Paths starting with `/` are relative to the in
+/// directory, others to the page. The result doesn't depend on whether the target exists.
+///
+/// Returns the source paths of the linked pages, and the links to pages missing from `sources`
+/// or outside the site as problems at the positions of the links.
+pub(crate) fn rewrite_links(
+    blocks: &mut [Block],
+    spans: &[SpanTree],
+    page: &Path,
+    sources: &[PathBuf],
+    options: &GenerateOptions,
+) -> (Vec<PathBuf>, Vec<MdParseError>) {
+    let mut rewriter = Rewriter {
+        page,
+        sources,
+        options,
+        targets: Vec::new(),
+        broken: Vec::new(),
+    };
+    rewriter.blocks(blocks, spans);
+    rewriter.targets.sort();
+    rewriter.targets.dedup();
+    (rewriter.targets, rewriter.broken)
+}
+
+impl Rewriter<'_> {
+    fn blocks(&mut self, blocks: &mut [Block], spans: &[SpanTree]) {
+        for (block, span) in blocks.iter_mut().zip(spans) {
+            match block {
+                Block::Paragraph(content) | Block::Heading { content, .. } => {
+                    self.inlines(content, &span.children);
+                }
+                Block::Quote(blocks) => self.blocks(blocks, &span.children),
+                Block::List(items) | Block::OrderedList { items, .. } => {
+                    for (item, span) in items.iter_mut().zip(&span.children) {
+                        self.blocks(&mut item.blocks, &span.children);
+                    }
+                }
+                Block::Table { header, rows, .. } => {
+                    let cells = header.iter_mut().chain(rows.iter_mut().flatten());
+                    for (cell, span) in cells.zip(&span.children) {
+                        self.inlines(cell, &span.children);
+                    }
+                }
+                Block::Footnotes(footnotes) => {
+                    for (footnote, span) in footnotes.iter_mut().zip(&span.children) {
+                        self.blocks(&mut footnote.blocks, &span.children);
+                    }
+                }
+                Block::Code { .. } | Block::Toc(_) | Block::ThematicBreak => {}
+            }
+        }
+    }
+
+    fn inlines(&mut self, inlines: &mut [Inline], spans: &[SpanTree]) {
+        for (inline, span) in inlines.iter_mut().zip(spans) {
+            match inline {
+                Inline::Bold(content)
+                | Inline::Italic(content)
+                | Inline::Strikethrough(content)
+                | Inline::Highlight(content)
+                | Inline::Subscript(content)
+                | Inline::Superscript(content) => {
+                    self.inlines(content, &span.children);
+                }
+                Inline::Link { text, href, .. } => {
+                    self.link(href, span);
+                    self.inlines(text, &span.children);
+                }
+                Inline::Text(_)
+                | Inline::Code(_)
+                | Inline::Kbd(_)
+                | Inline::Image { .. }
+                | Inline::LineBreak
+                | Inline::FootnoteRef { .. } => {}
+            }
+        }
+    }
+
+    fn link(&mut self, href: &mut String, span: &SpanTree) {
+        if is_external(href) {
+            return;
+        }
+        let end = href.find(['?', '#']).unwrap_or(href.len());
+        let (path, rest) = href.split_at(end);
+        let Some(extension) = Path::new(path).extension() else {
+            return;
+        };
+        if !self.options.is_markdown(Path::new(path)) {
+            return;
+        }
+
+        match resolve(self.page, path) {
+            Some(target) if self.sources.binary_search(&target).is_ok() => {
+                self.targets.push(target);
+            }
+            Some(target) => {
+                self.broken.push(
+                    MdParseError::new(format!("an existing page `{}`", target.display()), &href)
+                        .at(span.span.start),
+                );
+                // the page is rebuilt, and the link reported again, until the target exists
+                self.targets.push(target);
+            }
+            None => self
+                .broken
+                .push(MdParseError::new("a page inside the site", &href).at(span.span.start)),
+        }
+        let stem = &path[..path.len() - extension.len()];
+        *href = format!("{stem}html{rest}");
+    }
+}
+
+/// Whether the link leaves the site: it has a scheme, like `https:` or `mailto:`, or a host.
+/// Links within the page, like `#fragment`, are left alone as well.
+fn is_external(href: &str) -> bool {
+    let scheme = href.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    scheme || href.starts_with("//") || href.starts_with(['#', '?']) || href.is_empty()
+}
+
+/// Source path of a link from the page, `None` if it leads outside the in directory.
+fn resolve(page: &Path, link: &str) -> Option<PathBuf> {
+    let (mut resolved, link) = match link.strip_prefix('/') {
+        Some(link) => (PathBuf::new(), link),
+        None => (page.parent().unwrap_or(Path::new("")).to_path_buf(), link),
+    };
+    for component in Path::new(link).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{ParseOptions, parse_with};
+
+    fn rewrite(md: &str, page: &str) -> (String, Vec<PathBuf>, Vec<String>) {
+        let parsed = parse_with(md, &ParseOptions::default()).unwrap();
+        let mut blocks = parsed.document.blocks;
+        let sources = [
+            PathBuf::from("guide/install.md"),
+            PathBuf::from("guide/setup.md"),
+            PathBuf::from("index.md"),
+        ];
+        let (targets, broken) = rewrite_links(
+            &mut blocks,
+            &parsed.spans,
+            Path::new(page),
+            &sources,
+            &GenerateOptions::default(),
+        );
+        let html = crate::to_html::ToHtml::to_html(blocks);
+        let broken = broken.iter().map(ToString::to_string).collect();
+        (html, targets, broken)
+    }
+
+    #[test]
+    fn relative_links_to_pages() {
+        let (html, targets, broken) = rewrite(
+            "[a](setup.md) [b](../index.md#top) [c](/guide/install.md?v=1#x)",
+            "guide/intro.md",
+        );
+        assert_eq!(
+            html,
+            "<p><a href=\"setup.html\">a</a> <a href=\"../index.html#top\">b</a> <a href=\"/guide/install.html?v=1#x\">c</a></p>"
+        );
+        assert_eq!(
+            targets,
+            [
+                PathBuf::from("guide/install.md"),
+                PathBuf::from("guide/setup.md"),
+                PathBuf::from("index.md")
+            ]
+        );
+        assert!(broken.is_empty());
+    }
+
+    #[test]
+    fn other_links_are_kept() {
+        let md = "[a](https://example.com/a.md) [b](#setup.md) [c](style.css) [d](mailto:x@y.md) [e](//cdn.example.com/f.md) [f](guide/)";
+        let (html, targets, broken) = rewrite(md, "index.md");
+        assert_eq!(
+            html,
+            "<p><a href=\"https://example.com/a.md\">a</a> <a href=\"#setup.md\">b</a> <a href=\"style.css\">c</a> <a href=\"mailto:x@y.md\">d</a> <a href=\"//cdn.example.com/f.md\">e</a> <a href=\"guide/\">f</a></p>"
+        );
+        assert!(targets.is_empty());
+        assert!(broken.is_empty());
+    }
+
+    #[test]
+    fn missing_targets() {
+        let (html, targets, broken) = rewrite(
+            "# Links\n\n- see *[gone](gone.MD#x)*\n- and [out](../../up.md)",
+            "guide/intro.md",
+        );
+        assert_eq!(
+            html,
+            "<h1 id=\"links\">Links</h1><ul><li>see <b><a href=\"gone.html#x\">gone</a></b></li><li>and <a href=\"../../up.html\">out</a></li></ul>"
+        );
+        assert_eq!(targets, [PathBuf::from("guide/gone.MD")]);
+        assert_eq!(
+            broken,
+            [
+                "Parse error in '<unknown>' on line 3, column 8: expected 'an existing page `guide/gone.MD`', got 'gone.MD#x'",
+                "Parse error in '<unknown>' on line 4, column 7: expected 'a page inside the site', got '../../up.md'"
+            ]
+        );
+    }
+}
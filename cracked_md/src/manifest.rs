@@ -0,0 +1,177 @@
+//! Build manifest kept in the out directory, so unchanged sources can be skipped on the next run.
+//!
+//! The format is line based: a version line, the `layouts` and `config` fingerprints, then one
+//! tab separated `<mtime> <hash> <source> <output> <link>...` line per source file.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use fstools::crawl_fs;
+
+const FILE_NAME: &str = ".gravel-manifest";
+const VERSION_LINE: &str = "gravel-manifest 1";
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// Fingerprint of all templates.
+    pub layouts: u64,
+    /// Fingerprint of the generate options and the generator version.
+    pub config: u64,
+    /// Entries by source path, relative to the in directory.
+    pub entries: BTreeMap<PathBuf, Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Modification time of the source, in nanoseconds since the Unix epoch.
+    pub mtime: u128,
+    /// Hash of the source content.
+    pub hash: u64,
+    /// Output path, relative to the out directory.
+    pub output: PathBuf,
+    /// Source paths of the pages a page links to, relative to the in directory.
+    pub links: Vec<PathBuf>,
+}
+
+impl Manifest {
+    /// Loads the manifest of the last build. A missing or unreadable manifest is `None`, which
+    /// means everything is rebuilt.
+    pub fn load(outdir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(outdir.join(FILE_NAME)).ok()?;
+        let mut lines = content.lines();
+        if lines.next()? != VERSION_LINE {
+            return None;
+        }
+        let mut fingerprint = |name: &str| {
+            let (key, value) = lines.next()?.split_once(' ')?;
+            if key == name {
+                u64::from_str_radix(value, 16).ok()
+            } else {
+                None
+            }
+        };
+        let layouts = fingerprint("layouts")?;
+        let config = fingerprint("config")?;
+
+        let mut entries = BTreeMap::new();
+        for line in lines {
+            let mut fields = line.split('\t');
+            let (mtime, hash, source, output) = (
+                fields.next()?,
+                fields.next()?,
+                fields.next()?,
+                fields.next()?,
+            );
+            entries.insert(
+                source.into(),
+                Entry {
+                    mtime: mtime.parse().ok()?,
+                    hash: u64::from_str_radix(hash, 16).ok()?,
+                    output: output.into(),
+                    links: fields.map(PathBuf::from).collect(),
+                },
+            );
+        }
+        Some(Self {
+            layouts,
+            config,
+            entries,
+        })
+    }
+
+    pub fn save(&self, outdir: &Path) -> std::io::Result<()> {
+        let mut content = format!(
+            "{VERSION_LINE}\nlayouts {:016x}\nconfig {:016x}\n",
+            self.layouts, self.config
+        );
+        for (source, entry) in &self.entries {
+            content.push_str(&format!(
+                "{}\t{:016x}\t{}\t{}",
+                entry.mtime,
+                entry.hash,
+                source.display(),
+                entry.output.display()
+            ));
+            for link in &entry.links {
+                content.push_str(&format!("\t{}", link.display()));
+            }
+            content.push('\n');
+        }
+        fs::write(outdir.join(FILE_NAME), content)
+    }
+}
+
+/// 64-bit FNV-1a hash. Unlike the std hashers, it is stable between Rust versions, so it can be
+/// stored.
+pub fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Hashes the paths and contents of all files in the directory, in sorted order.
+pub fn dir_fingerprint(dir: &PathBuf) -> u64 {
+    let mut files: Vec<_> = crawl_fs(dir).into_iter().collect();
+    files.sort();
+    let mut all = Vec::new();
+    for file in files {
+        all.extend_from_slice(file.to_string_lossy().as_bytes());
+        all.push(0);
+        all.extend(fs::read(dir.join(&file)).unwrap_or_default());
+        all.push(0);
+    }
+    hash(&all)
+}
+
+/// Modification time of the file in nanoseconds since the Unix epoch, 0 if unavailable.
+pub fn mtime(path: &Path) -> u128 {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fnv1a_known_values() {
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("cracked_md-{}-manifest", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = Manifest {
+            layouts: 1,
+            config: u64::MAX,
+            entries: BTreeMap::from([(
+                PathBuf::from("guide/page one.md"),
+                Entry {
+                    mtime: 1_700_000_000_000_000_000,
+                    hash: 42,
+                    output: PathBuf::from("guide/page one.html"),
+                    links: vec![PathBuf::from("index.md"), PathBuf::from("guide/two.md")],
+                },
+            )]),
+        };
+
+        manifest.save(&dir).unwrap();
+        let loaded = Manifest::load(&dir).unwrap();
+
+        assert_eq!(loaded.layouts, manifest.layouts);
+        assert_eq!(loaded.config, manifest.config);
+        assert_eq!(loaded.entries, manifest.entries);
+    }
+}
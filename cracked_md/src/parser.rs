@@ -1,25 +1,336 @@
 //! Parse "Markdown" to AST.
 
 mod block;
+mod footnote;
+mod front_matter;
 mod inline;
+mod source;
+
+use std::collections::{HashMap, HashSet};
 
 use block::parse_blocks;
+use footnote::{Footnotes, resolve_footnotes};
+use front_matter::{parse_front_matter, parse_toml};
+use source::Source;
+
+use crate::{
+    MdParseError,
+    ast::{Block, Document, Metadata, SpanTree, TocEntry, plain_text},
+};
+
+/// Settings of [`parse_with`].
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Fail on the first problem, like an unclosed `*` or a heading without a space after the
+    /// `#`, instead of reading it as text and reporting a warning.
+    pub strict: bool,
+    pub dialect: Dialect,
+    pub extensions: Extensions,
+}
+
+/// Inline syntax on top of the dialect, on by default except for bare URLs. Delimiters that
+/// don't close are text, without a warning, so `a == b` or `~5 min` in prose stay as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extensions {
+    /// `~~deleted~~` text.
+    pub strikethrough: bool,
+    /// `==highlighted==` text.
+    pub highlight: bool,
+    /// `H~2~O`, without whitespace between the `~`.
+    pub subscript: bool,
+    /// `x^2^`, without whitespace between the `^`.
+    pub superscript: bool,
+    /// `[[Ctrl]]` keyboard keys.
+    pub kbd: bool,
+    /// Links from bare `https://` and `www.` URLs in the text. Off by default.
+    pub bare_urls: bool,
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Self {
+            strikethrough: true,
+            highlight: true,
+            subscript: true,
+            superscript: true,
+            kbd: true,
+            bare_urls: false,
+        }
+    }
+}
+
+impl Extensions {
+    /// No extensions.
+    pub const NONE: Self = Self {
+        strikethrough: false,
+        highlight: false,
+        subscript: false,
+        superscript: false,
+        kbd: false,
+        bare_urls: false,
+    };
+
+    /// Turns on the extensions with the given names: `strikethrough`, `highlight`, `subscript`,
+    /// `superscript`, `kbd` and `bare_urls`. `None` if a name is unknown.
+    #[must_use]
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        let mut extensions = Self::NONE;
+        for name in names {
+            let extension = match name.to_ascii_lowercase().as_str() {
+                "strikethrough" => &mut extensions.strikethrough,
+                "highlight" => &mut extensions.highlight,
+                "subscript" => &mut extensions.subscript,
+                "superscript" => &mut extensions.superscript,
+                "kbd" => &mut extensions.kbd,
+                "bare_urls" => &mut extensions.bare_urls,
+                _ => return None,
+            };
+            *extension = true;
+        }
+        Some(extensions)
+    }
+}
+
+/// The flavor of Markdown to read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// The syntax of gravel: `*bold*`, `_italic_`, tables and `[TOC]`.
+    #[default]
+    Gravel,
+    /// [CommonMark](https://commonmark.org): `*italic*`, `**bold**`, setext headings, indented
+    /// code blocks and thematic breaks, without the extensions of gravel.
+    CommonMark,
+}
+
+impl Dialect {
+    /// Reads a dialect from its name, `gravel` or `commonmark`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "gravel" => Some(Self::Gravel),
+            "commonmark" => Some(Self::CommonMark),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed document with where its parts came from and the problems found on the way.
+#[derive(Debug)]
+pub struct Parsed {
+    pub document: Document,
+    /// Spans of the top-level blocks, with the spans of the nodes inside them as described in
+    /// [`SpanTree`].
+    pub spans: Vec<SpanTree>,
+    /// Problems read as text or skipped, which would be errors with [`ParseOptions::strict`].
+    pub warnings: Vec<MdParseError>,
+}
+
+/// What the parsers share: the dialect, and where problems are reported, as errors in strict
+/// mode and warnings otherwise.
+pub(crate) struct ParseState {
+    strict: bool,
+    dialect: Dialect,
+    extensions: Extensions,
+    warnings: Vec<MdParseError>,
+    /// The matched CommonMark emphasis delimiters of the text being parsed, see
+    /// [`inline::match_emphasis`].
+    emphasis: HashMap<usize, (usize, usize)>,
+    /// The footnotes found so far, resolved once the whole document is parsed.
+    footnotes: Footnotes,
+    /// Destinations and titles of the link definitions, by their labels as [`label_key`] makes
+    /// them. The first definition of a label counts.
+    links: HashMap<String, (String, Option<String>)>,
+    /// Whether inlines are parsed. They are not in the first pass over the blocks, which only
+    /// collects the link definitions.
+    inlines: bool,
+}
 
-use crate::{MdParseError, ast::Document};
+impl ParseState {
+    pub(crate) fn commonmark(&self) -> bool {
+        self.dialect == Dialect::CommonMark
+    }
 
-/// Parses the incoming data to a Markdown abstract syntax tree.
+    /// Fails in strict mode, otherwise keeps the problem as a warning and lets the parser go on
+    /// with its lenient reading.
+    pub(crate) fn report(&mut self, problem: MdParseError) -> Result<(), MdParseError> {
+        if self.strict {
+            return Err(problem);
+        }
+        self.warnings.push(problem);
+        Ok(())
+    }
+}
+
+/// Parses the incoming data to a Markdown abstract syntax tree, starting with the optional front
+/// matter. Any problem is an error, see [`parse_with`] for a lenient parse.
 /// # Errors
 /// This function will return an `MdParseError` when any part of the input is invalid Markdown.
 pub fn parse(s: &str) -> Result<Document, MdParseError> {
-    Ok(Document {
-        blocks: parse_blocks(s)?,
+    parse_with(
+        s,
+        &ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        },
+    )
+    .map(|parsed| parsed.document)
+}
+
+/// Parses the incoming data with the given options. Unless strict, problems are warnings and
+/// a document is always returned: unclosed `*`, `_`, `` ` `` and `[` are text, like in
+/// CommonMark, a `#` without a space starts a paragraph, an unclosed code block runs to the end,
+/// and invalid tables and front matter are read as paragraphs.
+/// # Errors
+/// In strict mode, this function will return an `MdParseError` on the first problem.
+pub fn parse_with(s: &str, options: &ParseOptions) -> Result<Parsed, MdParseError> {
+    let mut state = ParseState {
+        strict: options.strict,
+        dialect: options.dialect,
+        extensions: options.extensions,
+        warnings: Vec::new(),
+        emphasis: HashMap::new(),
+        footnotes: Footnotes::default(),
+        links: HashMap::new(),
+        inlines: true,
+    };
+    let source = Source::new(s);
+    let lines: Vec<_> = s.lines().enumerate().collect();
+    let (metadata, front_matter_len) = match parse_front_matter(&lines) {
+        // CommonMark has no front matter, so only a valid one with keys counts, and a leading
+        // `---` is otherwise a thematic break or a setext underline
+        Ok((metadata, _)) if state.commonmark() && metadata.is_empty() => (Metadata::default(), 0),
+        Err(_) if state.commonmark() => (Metadata::default(), 0),
+        Ok(front_matter) => front_matter,
+        Err(e) => {
+            // the lines are read as the body, to not lose them
+            state.report(e)?;
+            (Metadata::default(), 0)
+        }
+    };
+
+    let lines = source.lines().split_off(front_matter_len);
+
+    // links may refer to definitions further down, so the blocks are read twice: first only for
+    // the link definitions, leniently and without inlines, then for everything
+    let mut first_pass = ParseState {
+        strict: false,
+        dialect: state.dialect,
+        extensions: state.extensions,
+        warnings: Vec::new(),
+        emphasis: HashMap::new(),
+        footnotes: Footnotes::default(),
+        links: HashMap::new(),
+        inlines: false,
+    };
+    parse_blocks(&source, lines.clone(), &mut first_pass)?;
+    state.links = first_pass.links;
+
+    let (mut blocks, mut spans) = parse_blocks(&source, lines, &mut state)?;
+    resolve_footnotes(&mut blocks, &mut spans, &source, &mut state)?;
+    let mut document = Document { metadata, blocks };
+    assign_heading_ids(&mut document.blocks, &mut HashSet::new());
+    let toc = document.toc();
+    fill_toc(&mut document.blocks, &toc);
+    Ok(Parsed {
+        document,
+        spans,
+        warnings: state.warnings,
     })
 }
 
+/// Parses `key = value` lines in the TOML subset of the front matter, e.g. a site config file.
+/// # Errors
+/// This function will return an `MdParseError` on the first invalid line.
+pub fn parse_metadata(s: &str) -> Result<Metadata, MdParseError> {
+    parse_toml(&s.lines().enumerate().collect::<Vec<_>>())
+}
+
+/// Link labels match regardless of case and of how whitespace is spread in them.
+fn label_key(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Turns heading text into a URL fragment: lowercase letters and digits of any script are kept,
+/// whitespace and hyphens become single hyphens, and other punctuation is dropped.
+#[must_use]
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() || c == '_' {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-') && !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(slug.trim_end_matches('-').len());
+    slug
+}
+
+/// Gives every heading a slug id in document order, suffixing repeated ones with `-1`, `-2`, …
+fn assign_heading_ids(blocks: &mut [Block], used: &mut HashSet<String>) {
+    for block in blocks {
+        match block {
+            Block::Heading { content, id, .. } => {
+                let mut slug = slugify(&plain_text(content));
+                if slug.is_empty() {
+                    slug = "section".to_string();
+                }
+                let mut unique = slug.clone();
+                let mut n = 1;
+                while used.contains(&unique) {
+                    unique = format!("{slug}-{n}");
+                    n += 1;
+                }
+                used.insert(unique.clone());
+                *id = unique;
+            }
+            Block::Quote(blocks) => assign_heading_ids(blocks, used),
+            Block::List(items) | Block::OrderedList { items, .. } => {
+                for item in items {
+                    assign_heading_ids(&mut item.blocks, used);
+                }
+            }
+            Block::Footnotes(footnotes) => {
+                for footnote in footnotes {
+                    assign_heading_ids(&mut footnote.blocks, used);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Puts the table of contents into every `[TOC]` marker.
+fn fill_toc(blocks: &mut [Block], toc: &[TocEntry]) {
+    for block in blocks {
+        match block {
+            Block::Toc(entries) => *entries = toc.to_vec(),
+            Block::Quote(blocks) => fill_toc(blocks, toc),
+            Block::List(items) | Block::OrderedList { items, .. } => {
+                for item in items {
+                    fill_toc(&mut item.blocks, toc);
+                }
+            }
+            Block::Footnotes(footnotes) => {
+                for footnote in footnotes {
+                    fill_toc(&mut footnote.blocks, toc);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::ast::*;
-    use crate::parser::parse;
+    use crate::parser::{Dialect, Extensions, ParseOptions, parse, parse_with};
+    use crate::to_html::ToHtml;
+    use std::collections::BTreeMap;
 
     #[test]
     fn only_paragraph() {
@@ -29,6 +340,7 @@ mod test {
         assert_eq!(
             doc,
             Document {
+                metadata: Metadata::default(),
                 blocks: vec![Block::Paragraph(vec![Inline::Text(
                     "testing paragraph".to_string()
                 )])]
@@ -45,14 +357,17 @@ mod test {
         assert_eq!(
             doc,
             Document {
+                metadata: Metadata::default(),
                 blocks: vec![
                     Block::Heading {
                         level: 1,
-                        content: vec![Inline::Text("Header 1".to_string())]
+                        content: vec![Inline::Text("Header 1".to_string())],
+                        id: "header-1".to_string()
                     },
                     Block::Heading {
                         level: 2,
-                        content: vec![Inline::Text("Header 2".to_string())]
+                        content: vec![Inline::Text("Header 2".to_string())],
+                        id: "header-2".to_string()
                     },
                 ]
             }
@@ -68,6 +383,7 @@ mod test {
         assert_eq!(
             doc,
             Document {
+                metadata: Metadata::default(),
                 blocks: vec![Block::Paragraph(vec![
                     Inline::Text("some ".to_string()),
                     Inline::Bold(vec![Inline::Text("bold".to_string())]),
@@ -88,6 +404,7 @@ mod test {
         assert_eq!(
             doc,
             Document {
+                metadata: Metadata::default(),
                 blocks: vec![Block::Paragraph(vec![
                     Inline::Text("run command ".to_string()),
                     Inline::Code("sudo rm -rf /".to_string()),
@@ -105,12 +422,14 @@ mod test {
         assert_eq!(
             doc,
             Document {
+                metadata: Metadata::default(),
                 blocks: vec![Block::Heading {
                     level: 1,
                     content: vec![
                         Inline::Text("Header is ".to_string()),
                         Inline::Bold(vec![Inline::Text("bold".to_string())])
-                    ]
+                    ],
+                    id: "header-is-bold".to_string()
                 }]
             }
         );
@@ -125,9 +444,11 @@ mod test {
         assert_eq!(
             doc,
             Document {
+                metadata: Metadata::default(),
                 blocks: vec![Block::Code {
                     language: None,
-                    content: "echo hello\n".to_string()
+                    content: "echo hello\n".to_string(),
+                    attributes: BTreeMap::new(),
                 }]
             }
         );
@@ -156,12 +477,1076 @@ mod test {
         assert_eq!(
             doc,
             Document {
+                metadata: Metadata::default(),
                 blocks: vec![Block::Code {
                     language: Some("rust".to_string()),
-                    content: "fn main() {\n\tprintln!(\"Hello world!\");\n}\n".to_string()
+                    content: "fn main() {\n\tprintln!(\"Hello world!\");\n}\n".to_string(),
+                    attributes: BTreeMap::new(),
+                }]
+            }
+        );
+    }
+
+    #[test]
+    fn code_block_attributes() {
+        let md = "```rust title=\"src/main.rs\" linenos hl_lines='1 3-4'\nfn main() {}\n```";
+
+        let doc = parse(md).unwrap();
+
+        assert_eq!(
+            doc.blocks,
+            vec![Block::Code {
+                language: Some("rust".to_string()),
+                content: "fn main() {}\n".to_string(),
+                attributes: BTreeMap::from([
+                    ("hl_lines".to_string(), "1 3-4".to_string()),
+                    ("linenos".to_string(), String::new()),
+                    ("title".to_string(), "src/main.rs".to_string()),
+                ]),
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_code_block_attributes() {
+        let md = "```rust hl_lines=\"x\" title=\"open\n```\n";
+
+        let parsed = parse_with(md, &ParseOptions::default()).unwrap();
+        let Block::Code { attributes, .. } = &parsed.document.blocks[0] else {
+            panic!("expected a code block");
+        };
+        assert_eq!(
+            attributes,
+            &BTreeMap::from([("title".to_string(), "open".to_string())])
+        );
+        let warnings: Vec<_> = parsed.warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            [
+                "Parse error in '<unknown>' on line 1, column 28: expected 'closing `\"`', got 'end of line'",
+                "Parse error in '<unknown>' on line 1, column 9: expected 'line numbers like `1 3-5` in `hl_lines`', got 'x'"
+            ]
+        );
+
+        let strict = ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        };
+        assert!(parse_with(md, &strict).is_err());
+    }
+
+    #[test]
+    fn reversed_hl_lines_range() {
+        let md = "```rust hl_lines=\"1 5-3\"\n```\n";
+
+        let parsed = parse_with(md, &ParseOptions::default()).unwrap();
+        let Block::Code { attributes, .. } = &parsed.document.blocks[0] else {
+            panic!("expected a code block");
+        };
+        assert!(attributes.is_empty());
+        let warnings: Vec<_> = parsed.warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            [
+                "Parse error in '<unknown>' on line 1, column 9: expected 'line numbers like `1 3-5` in `hl_lines`', got '1 5-3'"
+            ]
+        );
+    }
+
+    #[test]
+    fn unordered_list_markers() {
+        let md = "- one\n* two\n+ three";
+
+        let doc = parse(md).unwrap();
+
+        let item = |s: &str| ListItem {
+            blocks: vec![Block::Paragraph(vec![Inline::Text(s.to_string())])],
+            checked: None,
+        };
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![
+                    Block::List(vec![item("one")]),
+                    Block::List(vec![item("two")]),
+                    Block::List(vec![item("three")]),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn nested_unordered_list() {
+        let md = "- a\n  - b\n  - c\n- d";
+
+        let doc = parse(md).unwrap();
+
+        let item = |s: &str| ListItem {
+            blocks: vec![Block::Paragraph(vec![Inline::Text(s.to_string())])],
+            checked: None,
+        };
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![Block::List(vec![
+                    ListItem {
+                        blocks: vec![
+                            Block::Paragraph(vec![Inline::Text("a".to_string())]),
+                            Block::List(vec![item("b"), item("c")]),
+                        ],
+                        checked: None
+                    },
+                    item("d"),
+                ])]
+            }
+        );
+    }
+
+    #[test]
+    fn list_item_with_code_block() {
+        let md = "- run this:\n\n  ```sh\n  ls -la\n  ```\n- done";
+
+        let doc = parse(md).unwrap();
+
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![Block::List(vec![
+                    ListItem {
+                        blocks: vec![
+                            Block::Paragraph(vec![Inline::Text("run this:".to_string())]),
+                            Block::Code {
+                                language: Some("sh".to_string()),
+                                content: "ls -la\n".to_string(),
+                                attributes: BTreeMap::new(),
+                            },
+                        ],
+                        checked: None
+                    },
+                    ListItem {
+                        blocks: vec![Block::Paragraph(vec![Inline::Text("done".to_string())])],
+                        checked: None
+                    },
+                ])]
+            }
+        );
+    }
+
+    #[test]
+    fn list_ends_at_unindented_paragraph() {
+        let md = "- item\n\nparagraph";
+
+        let doc = parse(md).unwrap();
+
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![
+                    Block::List(vec![ListItem {
+                        blocks: vec![Block::Paragraph(vec![Inline::Text("item".to_string())])],
+                        checked: None
+                    }]),
+                    Block::Paragraph(vec![Inline::Text("paragraph".to_string())]),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn list_item_error_line_number() {
+        let md = "text\n- item\n  #oops";
+        let err = parse(md).unwrap_err();
+        assert!(err.to_string().contains("on line 3"));
+    }
+
+    #[test]
+    fn ordered_list_with_start() {
+        let md = "3. three\n4. four";
+
+        let doc = parse(md).unwrap();
+
+        let item = |s: &str| ListItem {
+            blocks: vec![Block::Paragraph(vec![Inline::Text(s.to_string())])],
+            checked: None,
+        };
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![Block::OrderedList {
+                    start: 3,
+                    items: vec![item("three"), item("four")]
+                }]
+            }
+        );
+    }
+
+    #[test]
+    fn ordered_list_delimiters_start_new_lists() {
+        let md = "1. a\n1) b";
+
+        let doc = parse(md).unwrap();
+
+        let item = |s: &str| ListItem {
+            blocks: vec![Block::Paragraph(vec![Inline::Text(s.to_string())])],
+            checked: None,
+        };
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![
+                    Block::OrderedList {
+                        start: 1,
+                        items: vec![item("a")]
+                    },
+                    Block::OrderedList {
+                        start: 1,
+                        items: vec![item("b")]
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn ordered_list_with_nested_unordered() {
+        let md = "1. first\n   - detail\n2. second";
+
+        let doc = parse(md).unwrap();
+
+        let item = |s: &str| ListItem {
+            blocks: vec![Block::Paragraph(vec![Inline::Text(s.to_string())])],
+            checked: None,
+        };
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![Block::OrderedList {
+                    start: 1,
+                    items: vec![
+                        ListItem {
+                            blocks: vec![
+                                Block::Paragraph(vec![Inline::Text("first".to_string())]),
+                                Block::List(vec![item("detail")]),
+                            ],
+                            checked: None
+                        },
+                        item("second"),
+                    ]
                 }]
             }
         );
     }
+
+    #[test]
+    fn multiline_quote_is_one_block() {
+        let md = "> # Title\n> - item\n>\n> ```\n> code\n> ```";
+
+        let doc = parse(md).unwrap();
+
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![Block::Quote(vec![
+                    Block::Heading {
+                        level: 1,
+                        content: vec![Inline::Text("Title".to_string())],
+                        id: "title".to_string()
+                    },
+                    Block::List(vec![ListItem {
+                        blocks: vec![Block::Paragraph(vec![Inline::Text("item".to_string())])],
+                        checked: None
+                    }]),
+                    Block::Code {
+                        language: None,
+                        content: "code\n".to_string(),
+                        attributes: BTreeMap::new(),
+                    },
+                ])]
+            }
+        );
+    }
+
+    #[test]
+    fn nested_quote() {
+        let md = "> outer\n> > inner";
+
+        let doc = parse(md).unwrap();
+
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![Block::Quote(vec![
+                    Block::Paragraph(vec![Inline::Text("outer".to_string())]),
+                    Block::Quote(vec![Block::Paragraph(vec![Inline::Text(
+                        "inner".to_string()
+                    )])]),
+                ])]
+            }
+        );
+    }
+
+    #[test]
+    fn quote_lazy_continuation() {
+        let md = "> quoted\nlazy\n\nafter";
+
+        let doc = parse(md).unwrap();
+
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![
+                    Block::Quote(vec![Block::Paragraph(vec![Inline::Text(
+                        "quoted\nlazy".to_string()
+                    )])]),
+                    Block::Paragraph(vec![Inline::Text("after".to_string())]),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn wrapped_lines_join_paragraph() {
+        let md = "first line\n  second line\n\nnew paragraph";
+
+        let doc = parse(md).unwrap();
+
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![
+                    Block::Paragraph(vec![Inline::Text("first line\nsecond line".to_string())]),
+                    Block::Paragraph(vec![Inline::Text("new paragraph".to_string())]),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn paragraph_interrupted_by_heading() {
+        let md = "text\n# Heading";
+
+        let doc = parse(md).unwrap();
+
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![
+                    Block::Paragraph(vec![Inline::Text("text".to_string())]),
+                    Block::Heading {
+                        level: 1,
+                        content: vec![Inline::Text("Heading".to_string())],
+                        id: "heading".to_string()
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn multiline_list_item() {
+        let md = "- wrapped\n  item\n- lazy\nitem";
+
+        let doc = parse(md).unwrap();
+
+        let item = |s: &str| ListItem {
+            blocks: vec![Block::Paragraph(vec![Inline::Text(s.to_string())])],
+            checked: None,
+        };
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![Block::List(vec![item("wrapped\nitem"), item("lazy\nitem")])]
+            }
+        );
+    }
+
+    #[test]
+    fn table_with_alignments() {
+        let md = "name | value\n--- | ---:\n`a` | 1\nb\n\nafter";
+
+        let doc = parse(md).unwrap();
+
+        let text = |s: &str| vec![Inline::Text(s.to_string())];
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![
+                    Block::Table {
+                        header: vec![text("name"), text("value")],
+                        alignments: vec![Alignment::None, Alignment::Right],
+                        rows: vec![
+                            vec![vec![Inline::Code("a".to_string())], text("1")],
+                            vec![text("b"), vec![]],
+                        ]
+                    },
+                    Block::Paragraph(text("after")),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn table_escaped_pipe() {
+        let md = "| a \\| b |\n| --- |";
+
+        let doc = parse(md).unwrap();
+
+        assert_eq!(
+            doc,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![Block::Table {
+                    header: vec![vec![Inline::Text("a | b".to_string())]],
+                    alignments: vec![Alignment::None],
+                    rows: vec![]
+                }]
+            }
+        );
+    }
+
+    #[test]
+    fn table_malformed_delimiter_row() {
+        let md = "text\n\n| a | b |\n| --- | -x- |";
+        let err = parse(md).unwrap_err();
+        assert!(err.to_string().contains("on line 4"));
+    }
+
+    #[test]
+    fn table_delimiter_column_mismatch() {
+        let md = "| a | b |\n| --- |";
+        let err = parse(md).unwrap_err();
+        assert!(err.to_string().contains("on line 2"));
+    }
+
+    #[test]
+    fn duplicate_and_unicode_heading_ids() {
+        let md = "# Größe & Ölkanne!\n## Setup\n## Setup\n# Setup-1";
+
+        let doc = parse(md).unwrap();
+
+        let ids: Vec<_> = doc
+            .blocks
+            .iter()
+            .filter_map(|b| match b {
+                Block::Heading { id, .. } => Some(id.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, vec!["größe-ölkanne", "setup", "setup-1", "setup-1-1"]);
+    }
+
+    #[test]
+    fn nested_toc() {
+        let md = "[TOC]\n# A\n### A.a\n## A.b\n# B";
+
+        let doc = parse(md).unwrap();
+
+        let entry = |level, text: &str, id: &str, children| TocEntry {
+            level,
+            text: text.to_string(),
+            id: id.to_string(),
+            children,
+        };
+        let toc = vec![
+            entry(
+                1,
+                "A",
+                "a",
+                vec![entry(3, "A.a", "aa", vec![]), entry(2, "A.b", "ab", vec![])],
+            ),
+            entry(1, "B", "b", vec![]),
+        ];
+        assert_eq!(doc.toc(), toc);
+        assert_eq!(doc.blocks[0], Block::Toc(toc));
+
+        // headings in quotes, lists and footnotes have anchors, so they are listed as well
+        let md = "# A\n> ## Quoted\n\n- item\n\n  ## Listed\n\nNote[^n]\n\n[^n]: Text\n\n    ## Noted\n\n[TOC]";
+        let doc = parse(md).unwrap();
+        let toc = vec![entry(
+            1,
+            "A",
+            "a",
+            vec![
+                entry(2, "Quoted", "quoted", vec![]),
+                entry(2, "Listed", "listed", vec![]),
+                entry(2, "Noted", "noted", vec![]),
+            ],
+        )];
+        assert_eq!(doc.toc(), toc);
+        assert!(doc.blocks.contains(&Block::Toc(toc)));
+    }
+
+    #[test]
+    fn front_matter_and_body_line_numbers() {
+        let md = "---\ntitle: Page\n---\n# Page\n#oops";
+
+        let err = parse(md).unwrap_err();
+        assert!(err.to_string().contains("on line 5"));
+
+        let doc = parse("+++\ntitle = \"Page\"\n+++\ntext").unwrap();
+        assert_eq!(doc.metadata.title(), Some("Page"));
+        assert_eq!(
+            doc.blocks,
+            vec![Block::Paragraph(vec![Inline::Text("text".to_string())])]
+        );
+    }
+
+    /// The source text a span covers.
+    fn spanned<'a>(source: &'a str, tree: &SpanTree) -> &'a str {
+        &source[tree.span.start.offset..tree.span.end.offset]
+    }
+
+    #[test]
+    fn spans_of_blocks_and_inlines() {
+        let md = "+++\ntitle = \"x\"\n+++\n# Some *bold*\n\n> quoted\n> [a `link`](b.html)\n\n- one\n- two  \n  lines\n\n| a | b |\n|---|---|\n| é | *c* |\n";
+        let spans = parse_with(md, &ParseOptions::default()).unwrap().spans;
+
+        let blocks: Vec<_> = spans.iter().map(|s| spanned(md, s)).collect();
+        assert_eq!(
+            blocks,
+            [
+                "# Some *bold*",
+                "> quoted\n> [a `link`](b.html)",
+                "- one\n- two  \n  lines",
+                "| a | b |\n|---|---|\n| é | *c* |"
+            ]
+        );
+        assert_eq!(
+            spans[0].span.start,
+            Position {
+                offset: 20,
+                line: 4,
+                col: 1
+            }
+        );
+
+        // heading inlines
+        let heading: Vec<_> = spans[0].children.iter().map(|s| spanned(md, s)).collect();
+        assert_eq!(heading, ["Some ", "*bold*"]);
+        assert_eq!(spanned(md, &spans[0].children[1].children[0]), "bold");
+
+        // a paragraph over two quoted lines, the link text inside it
+        let paragraph = &spans[1].children[0];
+        assert_eq!(spanned(md, paragraph), "quoted\n> [a `link`](b.html)");
+        let link = &paragraph.children[1];
+        assert_eq!(spanned(md, link), "[a `link`](b.html)");
+        assert_eq!((link.span.start.line, link.span.start.col), (7, 3));
+        assert_eq!(spanned(md, &link.children[1]), "`link`");
+
+        // list items, with a hard line break
+        let items: Vec<_> = spans[2].children.iter().map(|s| spanned(md, s)).collect();
+        assert_eq!(items, ["- one", "- two  \n  lines"]);
+        let inlines: Vec<_> = spans[2].children[1].children[0]
+            .children
+            .iter()
+            .map(|s| spanned(md, s))
+            .collect();
+        assert_eq!(inlines, ["two", "  \n  ", "lines"]);
+
+        // table cells, columns counted in characters
+        let cells: Vec<_> = spans[3].children.iter().map(|s| spanned(md, s)).collect();
+        assert_eq!(cells, ["a", "b", "é", "*c*"]);
+        assert_eq!(spans[3].children[3].span.start.col, 7);
+    }
+
+    #[test]
+    fn errors_have_columns() {
+        let err = parse("text\n\n> quote with [link](a.html").unwrap_err();
+        assert!(err.to_string().contains("on line 3, column 27"));
+
+        let err = parse("| a | b |\n| --- | -x- |").unwrap_err();
+        assert!(err.to_string().contains("on line 2, column 9"));
+    }
+
+    #[test]
+    fn lenient_block_problems() {
+        let md = "+++\ntitle = oops\n+++\n#tag\n\n| a | b |\n| --- |\n\n```rust\nfn main() {}";
+        assert!(parse(md).is_err());
+
+        let parsed = parse_with(md, &ParseOptions::default()).unwrap();
+        assert_eq!(
+            parsed.document,
+            Document {
+                metadata: Metadata::default(),
+                blocks: vec![
+                    Block::Paragraph(vec![Inline::Text("+++\ntitle = oops\n+++".to_string())]),
+                    Block::Paragraph(vec![Inline::Text("#tag".to_string())]),
+                    Block::Paragraph(vec![Inline::Text("| a | b |\n| --- |".to_string())]),
+                    Block::Code {
+                        language: Some("rust".to_string()),
+                        content: "fn main() {}\n".to_string(),
+                        attributes: BTreeMap::new(),
+                    }
+                ]
+            }
+        );
+        let lines: Vec<_> = parsed
+            .warnings
+            .iter()
+            .map(|w| w.to_string().split(':').next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "Parse error in '<unknown>' on line 2, column 9",
+                "Parse error in '<unknown>' on line 4, column 2",
+                "Parse error in '<unknown>' on line 7, column 1",
+                "Parse error in '<unknown>' on line 9, column 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn lenient_invalid_front_matter_is_body() {
+        let commonmark = ParseOptions {
+            dialect: Dialect::CommonMark,
+            ..ParseOptions::default()
+        };
+        let parsed = parse_with("---\nFoo\n---\nBar", &commonmark).unwrap();
+        assert_eq!(
+            parsed.document.blocks.to_html(),
+            "<hr><h2 id=\"foo\">Foo</h2><p>Bar</p>"
+        );
+
+        let parsed =
+            parse_with("---\n\nSome text\n\n---\n\nEnd", &ParseOptions::default()).unwrap();
+        assert_eq!(parsed.warnings.len(), 1);
+        let html = parsed.document.blocks.to_html();
+        assert!(html.contains("<p>Some text</p>"));
+        assert!(html.contains("<p>End</p>"));
+    }
+
+    #[test]
+    fn empty_list_items_are_kept() {
+        let doc = parse_with("1. a\n2.\n3. b\n\n-", &ParseOptions::default())
+            .unwrap()
+            .document;
+        assert_eq!(
+            doc.blocks.to_html(),
+            "<ol><li>a</li><li></li><li>b</li></ol><ul><li></li></ul>"
+        );
+
+        // unlike one that only held a definition
+        let doc = parse_with("- [a]: /a.html\n- b\n\n[a]", &ParseOptions::default())
+            .unwrap()
+            .document;
+        assert_eq!(
+            doc.blocks.to_html(),
+            "<ul><li>b</li></ul><p><a href=\"/a.html\">a</a></p>"
+        );
+    }
+
+    #[test]
+    fn commonmark_front_matter() {
+        let commonmark = ParseOptions {
+            dialect: Dialect::CommonMark,
+            strict: true,
+            ..ParseOptions::default()
+        };
+        let doc = parse_with("---\ntitle: Page\n---\ntext", &commonmark)
+            .unwrap()
+            .document;
+        assert_eq!(doc.metadata.title(), Some("Page"));
+        assert_eq!(doc.blocks.to_html(), "<p>text</p>");
+
+        // without keys, or with lines that are no front matter, `---` is Markdown
+        let doc = parse_with("---\n---", &commonmark).unwrap().document;
+        assert_eq!(doc.blocks.to_html(), "<hr><hr>");
+        let parsed = parse_with("---\nFoo\n---\nBar", &commonmark).unwrap();
+        assert!(parsed.warnings.is_empty());
+        assert_eq!(
+            parsed.document.blocks.to_html(),
+            "<hr><h2 id=\"foo\">Foo</h2><p>Bar</p>"
+        );
+    }
+
+    #[test]
+    fn reference_links() {
+        let md = "[Full][The  Ref], [collapsed][], [shortcut] and ![image][logo].\n\n- [the ref]: /a.html \"A\"\n\n[Collapsed]: <b c.html>\n[shortcut]: c.html 'C'\n[logo]: logo.png (Logo)\n[the ref]: ignored.html";
+
+        let doc = parse(md).unwrap();
+
+        let link = |content: &str, href: &str, title: Option<&str>| Inline::Link {
+            text: vec![text(content)],
+            href: href.to_string(),
+            title: title.map(str::to_string),
+        };
+        assert_eq!(
+            doc.blocks,
+            vec![
+                Block::Paragraph(vec![
+                    link("Full", "/a.html", Some("A")),
+                    text(", "),
+                    link("collapsed", "b c.html", None),
+                    text(", "),
+                    link("shortcut", "c.html", Some("C")),
+                    text(" and "),
+                    Inline::Image {
+                        alt: "image".to_string(),
+                        src: "logo.png".to_string(),
+                        title: Some("Logo".to_string()),
+                    },
+                    text("."),
+                ]),
+                // the list with only a definition in it is left out
+            ]
+        );
+
+        // CommonMark keeps the empty list item, and only `<>` is an empty destination
+        let md = "- [a]: /a.html\n- b\n\n[<>]:\n\n[c]: <> \"<>\"\n\n[a] [c]";
+        let options = ParseOptions {
+            dialect: Dialect::CommonMark,
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            parse_with(md, &options).unwrap().document.blocks,
+            vec![
+                Block::List(vec![
+                    ListItem {
+                        blocks: Vec::new(),
+                        checked: None
+                    },
+                    ListItem {
+                        blocks: vec![Block::Paragraph(vec![text("b")])],
+                        checked: None
+                    },
+                ]),
+                Block::Paragraph(vec![text("[<>]:")]),
+                Block::Paragraph(vec![
+                    link("a", "/a.html", None),
+                    text(" "),
+                    link("c", "", Some("<>")),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn undefined_references() {
+        let md = "[text][nowhere] and [other]\n\n[unused]: x.html";
+        let err = parse(md).unwrap_err();
+        assert!(
+            err.to_string().contains(
+                "on line 1, column 7: expected '(<href>) or a defined reference', got '['"
+            )
+        );
+
+        let parsed = parse_with(md, &ParseOptions::default()).unwrap();
+        assert_eq!(
+            parsed.document.blocks,
+            vec![Block::Paragraph(vec![text("[text][nowhere] and [other]")])]
+        );
+        assert_eq!(parsed.warnings.len(), 3);
+
+        let err = parse("a [link](b.html").unwrap_err();
+        assert!(err.to_string().ends_with("expected ')', got 'end of text'"));
+        let err = parse("a [link]").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("expected '(<href>) or a defined reference', got 'end of text'")
+        );
+    }
+
+    #[test]
+    fn task_lists() {
+        let md = "- [ ] open\n- [X] done\n  1. [x] nested\n  2. [ ]\n- [x]not a task\n- plain\n\n> - [ ] quoted";
+
+        // `[x]not a task` is a link without a target
+        let doc = parse_with(md, &ParseOptions::default()).unwrap().document;
+
+        let task = |checked, s: &str| ListItem {
+            blocks: if s.is_empty() {
+                Vec::new()
+            } else {
+                vec![Block::Paragraph(vec![text(s)])]
+            },
+            checked,
+        };
+        assert_eq!(
+            doc.blocks[0],
+            Block::List(vec![
+                task(Some(false), "open"),
+                ListItem {
+                    blocks: vec![
+                        Block::Paragraph(vec![text("done")]),
+                        Block::OrderedList {
+                            start: 1,
+                            items: vec![task(Some(true), "nested"), task(Some(false), "")],
+                        },
+                    ],
+                    checked: Some(true),
+                },
+                task(None, "[x]not a task"),
+                task(None, "plain"),
+            ])
+        );
+        assert_eq!(doc.tasks(), Tasks { open: 3, done: 2 });
+        assert_eq!(doc.tasks().total(), 5);
+    }
+
+    #[test]
+    fn syntax_extensions_turned_off() {
+        let md = "~~a~~ ==b== c~d~ e^f^ [[g]]";
+        let options = |extensions| ParseOptions {
+            extensions,
+            ..ParseOptions::default()
+        };
+
+        let none = parse_with(md, &options(Extensions::NONE)).unwrap();
+        assert_eq!(
+            none.document.blocks,
+            vec![Block::Paragraph(vec![text("~~a~~ ==b== c~d~ e^f^ [[g]]")])]
+        );
+        // `[[g]]` is a link without a target
+        assert_eq!(none.warnings.len(), 1);
+
+        let some = Extensions::from_names(["subscript", "KBD"]).unwrap();
+        assert_eq!(
+            parse_with(md, &options(some)).unwrap().document.blocks,
+            vec![Block::Paragraph(vec![
+                // a subscript doesn't start or end next to another `~`
+                text("~~a~~ ==b== c"),
+                Inline::Subscript(vec![text("d")]),
+                text(" e^f^ "),
+                Inline::Kbd("g".to_string()),
+            ])]
+        );
+        assert_eq!(Extensions::from_names(["blink"]), None);
+    }
+
+    #[test]
+    fn bare_urls() {
+        let md = "Go to www.example.com, (see https://example.com/a_b_c) or *https://x.org*.";
+        let options = ParseOptions {
+            extensions: Extensions {
+                bare_urls: true,
+                ..Extensions::default()
+            },
+            ..ParseOptions::default()
+        };
+        let link = |url: &str, href: &str| Inline::Link {
+            text: vec![text(url)],
+            href: href.to_string(),
+            title: None,
+        };
+        assert_eq!(
+            parse_with(md, &options).unwrap().document.blocks,
+            vec![Block::Paragraph(vec![
+                text("Go to "),
+                link("www.example.com", "http://www.example.com"),
+                text(", (see "),
+                link("https://example.com/a_b_c", "https://example.com/a_b_c"),
+                text(") or "),
+                Inline::Bold(vec![link("https://x.org", "https://x.org")]),
+                text("."),
+            ])]
+        );
+        assert_eq!(
+            parse_with("Go to www.example.com.", &ParseOptions::default())
+                .unwrap()
+                .document
+                .blocks,
+            vec![Block::Paragraph(vec![text("Go to www.example.com.")])]
+        );
+    }
+
+    #[test]
+    fn footnotes() {
+        let md = "Text[^b] and *more*[^A], again[^b].\n\n[^a]: First\nlazy line.\n\n    - item\n[^b]: Back to [^a].";
+
+        let doc = parse(md).unwrap();
+
+        let reference = |label: &str, number, occurrence| Inline::FootnoteRef {
+            label: label.to_string(),
+            number,
+            occurrence,
+        };
+        assert_eq!(
+            doc.blocks,
+            vec![
+                Block::Paragraph(vec![
+                    text("Text"),
+                    reference("b", 1, 1),
+                    text(" and "),
+                    Inline::Bold(vec![text("more")]),
+                    reference("A", 2, 1),
+                    text(", again"),
+                    reference("b", 1, 2),
+                    text("."),
+                ]),
+                Block::Footnotes(vec![
+                    Footnote {
+                        label: "b".to_string(),
+                        number: 1,
+                        blocks: vec![Block::Paragraph(vec![
+                            text("Back to "),
+                            reference("a", 2, 2),
+                            text("."),
+                        ])],
+                        references: 2,
+                    },
+                    Footnote {
+                        label: "a".to_string(),
+                        number: 2,
+                        blocks: vec![
+                            Block::Paragraph(vec![text("First\nlazy line.")]),
+                            Block::List(vec![ListItem {
+                                blocks: vec![Block::Paragraph(vec![text("item")])],
+                                checked: None
+                            }]),
+                        ],
+                        references: 2,
+                    },
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn footnote_problems() {
+        let md = "See [^missing] and [^a].\n\n[^a]: One\n[^unused]: Two\n[^A]: Three";
+        assert!(parse(md).is_err());
+
+        let parsed = parse_with(md, &ParseOptions::default()).unwrap();
+        assert_eq!(
+            parsed.document.blocks[0],
+            Block::Paragraph(vec![
+                text("See "),
+                text("[^missing]"),
+                text(" and "),
+                Inline::FootnoteRef {
+                    label: "a".to_string(),
+                    number: 1,
+                    occurrence: 1,
+                },
+                text("."),
+            ])
+        );
+        let Block::Footnotes(footnotes) = &parsed.document.blocks[1] else {
+            panic!("expected footnotes");
+        };
+        assert_eq!(footnotes.len(), 1);
+        assert_eq!(footnotes[0].blocks, [Block::Paragraph(vec![text("One")])]);
+
+        let warnings: Vec<_> = parsed.warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            warnings,
+            [
+                "Parse error in '<unknown>' on line 5, column 1: expected 'a single definition of `[^A]`', got 'another definition'",
+                "Parse error in '<unknown>' on line 1, column 5: expected 'a definition of `[^missing]`', got 'no definition'",
+                "Parse error in '<unknown>' on line 4, column 1: expected 'a reference to `[^unused]`', got 'no reference'"
+            ]
+        );
+    }
+
+    fn parse_commonmark(md: &str) -> Vec<Block> {
+        let options = ParseOptions {
+            dialect: Dialect::CommonMark,
+            ..ParseOptions::default()
+        };
+        let parsed = parse_with(md, &options).unwrap();
+        assert!(parsed.warnings.is_empty());
+        parsed.document.blocks
+    }
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(s.to_string())
+    }
+
+    #[test]
+    fn commonmark_emphasis() {
+        assert_eq!(
+            parse_commonmark("*a* **b** _c_ __d__ ***e*** snake_case_name"),
+            vec![Block::Paragraph(vec![
+                Inline::Italic(vec![text("a")]),
+                text(" "),
+                Inline::Bold(vec![text("b")]),
+                text(" "),
+                Inline::Italic(vec![text("c")]),
+                text(" "),
+                Inline::Bold(vec![text("d")]),
+                text(" "),
+                Inline::Italic(vec![Inline::Bold(vec![text("e")])]),
+                text(" snake_case_name"),
+            ])]
+        );
+        assert_eq!(
+            parse_commonmark("*foo**bar**baz* and *unclosed"),
+            vec![Block::Paragraph(vec![
+                Inline::Italic(vec![
+                    text("foo"),
+                    Inline::Bold(vec![text("bar")]),
+                    text("baz")
+                ]),
+                text(" and *unclosed"),
+            ])]
+        );
+    }
+
+    #[test]
+    fn commonmark_code_and_escapes() {
+        assert_eq!(
+            parse_commonmark("`` a ` b `` \\*not\\* \\q [no target]"),
+            vec![Block::Paragraph(vec![
+                Inline::Code("a ` b".to_string()),
+                text(" *not* \\q [no target]"),
+            ])]
+        );
+    }
+
+    #[test]
+    fn commonmark_blocks() {
+        let md = "Title\n=====\n\n## ATX ##\n\n    indented\n\n      code\n\n* * *\n\n~~~~ rust ignore\n```\n~~~~\nSub\n---";
+        assert_eq!(
+            parse_commonmark(md),
+            vec![
+                Block::Heading {
+                    level: 1,
+                    content: vec![text("Title")],
+                    id: "title".to_string()
+                },
+                Block::Heading {
+                    level: 2,
+                    content: vec![text("ATX")],
+                    id: "atx".to_string()
+                },
+                Block::Code {
+                    language: None,
+                    content: "indented\n\n  code\n".to_string(),
+                    attributes: BTreeMap::new(),
+                },
+                Block::ThematicBreak,
+                Block::Code {
+                    language: Some("rust".to_string()),
+                    content: "```\n".to_string(),
+                    attributes: BTreeMap::from([("ignore".to_string(), String::new())]),
+                },
+                Block::Heading {
+                    level: 2,
+                    content: vec![text("Sub")],
+                    id: "sub".to_string()
+                },
+            ]
+        );
+        // gravel syntax is plain text in CommonMark
+        assert_eq!(
+            parse_commonmark("#tag\n[TOC]"),
+            vec![Block::Paragraph(vec![text("#tag\n[TOC]")])]
+        );
+    }
 }
 // */
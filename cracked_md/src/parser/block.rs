@@ -1,107 +1,914 @@
 use super::inline::parse_inlines;
-use crate::{MdParseError, ast::Block};
+use super::{
+    ParseState,
+    footnote::{Definition, footnote_label},
+    label_key,
+    source::{Line, MappedText, Source},
+};
+use crate::{
+    MdParseError,
+    ast::{Alignment, Block, Inline, ListItem, SpanTree, parse_line_ranges},
+};
 
 use crate::parse_trait::Parse;
+use std::{collections::BTreeMap, iter::Peekable};
 
-pub fn parse_blocks(input: &str) -> Result<Vec<Block>, MdParseError> {
+/// Parses blocks from lines paired with their byte offsets in the source. Container blocks (list
+/// items, quotes) strip their markers and recurse with the remaining parts of the lines, so spans
+/// and reported positions always point to the original input.
+pub fn parse_blocks(
+    source: &Source,
+    input: Vec<Line>,
+    state: &mut ParseState,
+) -> Result<(Vec<Block>, Vec<SpanTree>), MdParseError> {
     let mut blocks = Vec::new();
-    let mut lines = input.lines().enumerate().peekable();
+    let mut spans = Vec::new();
+    let mut lines = input.into_iter().peekable();
 
     while let Some((i, line)) = lines.next() {
         let mut line_chars = line.chars().peekable();
 
         // empty line
-        if line_chars.peek().is_none() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // CommonMark leaf blocks, in place of the headings and code blocks of gravel
+        if state.commonmark()
+            && let Some(((block, children), end)) =
+                commonmark_leaf(source, (i, line), &mut lines, state)?
+        {
+            blocks.push(block);
+            spans.push(source.span(i, end, children));
             continue;
         }
 
         // header
         let mut heading_level = 0;
-        while line_chars.parse_token('#') {
+        while !state.commonmark() && line_chars.parse_token('#') {
             if heading_level < 6 {
                 heading_level += 1;
             }
         }
-        if heading_level > 0 {
-            if !line_chars.parse_token(' ') {
-                Err(MdParseError::from_line(
-                    i + 1,
-                    "<space> after #",
-                    "no <space>",
-                ))?;
-            }
-            let line_content: String = line_chars.collect();
+        let hashes = line.len() - line.trim_start_matches('#').len();
+        if heading_level > 0 && !line_chars.parse_token(' ') {
+            // read as a paragraph
+            state.report(
+                MdParseError::new("<space> after #", "no <space>").at(source.position(i + hashes)),
+            )?;
+        } else if heading_level > 0 {
+            let content = MappedText::from_line(source, tail((i, line), &line[hashes + 1..]));
+            let (content, children) = parse_inlines(&content, state)?;
             blocks.push(Block::Heading {
                 level: heading_level,
-                content: parse_inlines(&line_content)?,
+                content,
+                // assigned once the whole document is parsed
+                id: String::new(),
             });
+            spans.push(source.span(i, i + line.len(), children));
             continue;
         }
 
-        // quote TODO
-        /*
-        if line_chars.parse_str("> ") {
-            let content: String = line_chars.collect();
-            let quote_blocks = parse_blocks(&content).map_err(|e| e.set_line(i + 1))?;
-            blocks.push(Block::Quote(quote_blocks));
+        // table of contents marker
+        if !state.commonmark() && line.trim() == "[TOC]" {
+            blocks.push(Block::Toc(Vec::new()));
+            spans.push(source.span(i, i + line.len(), Vec::new()));
+            continue;
+        }
+
+        // footnote definition, continued by indented lines and lazy paragraph lines
+        if !state.commonmark()
+            && let Some(label) = footnote_definition(line)
+        {
+            let content = &line[label.len() + 4..];
+            let mut definition_lines = vec![tail((i, line), content.trim_start())];
+            let mut blank_lines = Vec::new();
+            let mut end = i + line.len();
+
+            while let Some(&(j, next)) = lines.peek() {
+                if next.trim().is_empty() {
+                    blank_lines.push((j, ""));
+                } else if indent_width(next) >= 4 {
+                    definition_lines.append(&mut blank_lines);
+                    definition_lines.push(tail((j, next), strip_indent(next, 4)));
+                    end = j + next.len();
+                } else if blank_lines.is_empty() && !starts_block(next, state) {
+                    definition_lines.push(tail((j, next), next.trim_start()));
+                    end = j + next.len();
+                } else {
+                    break;
+                }
+                lines.next();
+            }
+            let (blocks, children) = parse_blocks(source, definition_lines, state)?;
+            state.footnotes.definitions.push(Definition {
+                label: label.to_string(),
+                blocks,
+                span: source.span(i, end, children),
+            });
+            continue;
+        }
+
+        // quote
+        if let Some(content) = quote_content(line) {
+            let mut quote_lines = vec![(i + content, &line[content..])];
+            let mut lazy_allowed = !line[content..].trim().is_empty();
+            let mut end = i + line.len();
+
+            while let Some(&(j, next)) = lines.peek() {
+                if let Some(content) = quote_content(next) {
+                    lazy_allowed = !next[content..].trim().is_empty();
+                    quote_lines.push((j + content, &next[content..]));
+                } else if lazy_allowed && !next.trim().is_empty() && !starts_block(next, state) {
+                    // lazy continuation of the quote's last paragraph
+                    quote_lines.push(tail((j, next), next.trim_start()));
+                } else {
+                    break;
+                }
+                end = j + next.len();
+                lines.next();
+            }
+            let (quote, children) = parse_blocks(source, quote_lines, state)?;
+            blocks.push(Block::Quote(quote));
+            spans.push(source.span(i, end, children));
             continue;
         }
-        */
 
-        // unordered list TODO
-        if line_chars.parse_str("- ") {
-            todo!()
+        // list
+        if let Some(marker) = list_marker(line) {
+            let mut items = Vec::new();
+            let mut item_spans = Vec::new();
+            let list_start = i + line.len() - line.trim_start().len();
+            let mut item_start = list_start;
+            let mut item_end = i + line.len();
+            let (mut checked, first_line) = task_marker(tail((i, line), &line[marker.content..]));
+            let mut item_lines = vec![first_line];
+            let mut content_indent = marker.content;
+            let mut blank_lines = Vec::new();
+            // whether each item had nothing but definitions in it, which gravel leaves out
+            let mut definitions_only = Vec::new();
+            let has_content =
+                |lines: &[Line]| lines.iter().any(|(_, line)| !line.trim().is_empty());
+
+            while let Some(&(j, next)) = lines.peek() {
+                if next.trim().is_empty() {
+                    blank_lines.push((j, ""));
+                } else if indent_width(next) >= content_indent {
+                    item_lines.append(&mut blank_lines);
+                    item_lines.push(tail((j, next), strip_indent(next, content_indent)));
+                    item_end = j + next.len();
+                } else if let Some(next_marker) = list_marker(next)
+                    && next_marker.kind.continues(&marker.kind)
+                    && !(state.commonmark() && is_thematic_break(next))
+                {
+                    let content = has_content(&item_lines);
+                    let (item, children) =
+                        parse_blocks(source, std::mem::take(&mut item_lines), state)?;
+                    definitions_only.push(content && item.is_empty() && checked.is_none());
+                    items.push(ListItem {
+                        blocks: item,
+                        checked,
+                    });
+                    item_spans.push(source.span(item_start, item_end, children));
+                    blank_lines.clear();
+                    let (next_checked, first_line) =
+                        task_marker(tail((j, next), &next[next_marker.content..]));
+                    checked = next_checked;
+                    item_lines.push(first_line);
+                    item_start = j + next.len() - next.trim_start().len();
+                    item_end = j + next.len();
+                    content_indent = next_marker.content;
+                } else if blank_lines.is_empty() && !starts_block(next, state) {
+                    // lazy continuation of the item's last paragraph
+                    item_lines.push(tail((j, next), next.trim_start()));
+                    item_end = j + next.len();
+                } else {
+                    break;
+                }
+                lines.next();
+            }
+            let content = has_content(&item_lines);
+            let (item, children) = parse_blocks(source, item_lines, state)?;
+            definitions_only.push(content && item.is_empty() && checked.is_none());
+            items.push(ListItem {
+                blocks: item,
+                checked,
+            });
+            item_spans.push(source.span(item_start, item_end, children));
+            // items that only held link definitions are left out, CommonMark keeps them empty
+            let (items, item_spans): (Vec<_>, Vec<_>) = items
+                .into_iter()
+                .zip(item_spans)
+                .zip(definitions_only)
+                .filter(|(_, only)| state.commonmark() || !only)
+                .map(|(item, _)| item)
+                .unzip();
+            if items.is_empty() {
+                continue;
+            }
+            blocks.push(match marker.kind {
+                ListKind::Bullet(_) => Block::List(items),
+                ListKind::Ordered { start, .. } => Block::OrderedList { start, items },
+            });
+            spans.push(source.span(list_start, item_end, item_spans));
+            continue;
         }
 
         // code
-        if line_chars.parse_str("```") {
-            let lang_line: String = line_chars.collect();
-            let lang = if lang_line.is_empty() {
-                None
-            } else {
-                Some(lang_line)
-            };
+        if !state.commonmark() && line_chars.parse_str("```") {
+            let (lang, attributes) = info_string(source, tail((i, line), &line[3..]), state)?;
             let mut code = String::new();
 
-            let mut successful = false;
+            let mut end = None;
+            let mut last_line_end = i + line.len();
             for (j, line) in lines.by_ref() {
                 let mut code_line_chars = line.chars().peekable();
                 // code block end
                 if code_line_chars.parse_str("```") {
                     let remaining: String = code_line_chars.collect();
                     if remaining.is_empty() {
-                        blocks.push(Block::Code {
-                            language: lang,
-                            content: code,
-                        });
-                        successful = true;
+                        end = Some(j + line.len());
                         break;
                     }
-                    Err(MdParseError::from_line(
-                        j + 1,
-                        "```",
-                        format!("```{remaining}"),
-                    ))?;
+                    // read as a line of code
+                    state.report(
+                        MdParseError::new("```", format!("```{remaining}"))
+                            .at(source.position(j + 3)),
+                    )?;
+                }
+                code.push_str(line);
+                code.push('\n');
+                last_line_end = j + line.len();
+            }
+            let end = match end {
+                Some(end) => end,
+                None => {
+                    // the code block runs to the end
+                    state.report(
+                        MdParseError::new("a terminating '```'", "").at(source.position(i)),
+                    )?;
+                    last_line_end
+                }
+            };
+            blocks.push(Block::Code {
+                language: lang,
+                content: code,
+                attributes,
+            });
+            spans.push(source.span(i, end, Vec::new()));
+            continue;
+        }
+
+        // table, a header row followed by a delimiter row
+        if !state.commonmark()
+            && line.contains('|')
+            && let Some(&(j, delimiter_line)) = lines.peek()
+            && is_delimiter_row(line, delimiter_line)
+            && let Some(alignments) =
+                table_alignments(source, (i, line), (j, delimiter_line), state)?
+        {
+            lines.next();
+            let (header, mut children) = parse_row(source, (i, line), alignments.len(), state)?;
+
+            let mut rows = Vec::new();
+            let mut end = j + delimiter_line.len();
+            while let Some(&(k, row)) = lines.peek() {
+                if row.trim().is_empty() || starts_block(row, state) {
+                    break;
+                }
+                let (cells, cell_spans) = parse_row(source, (k, row), alignments.len(), state)?;
+                rows.push(cells);
+                children.extend(cell_spans);
+                end = k + row.len();
+                lines.next();
+            }
+            blocks.push(Block::Table {
+                header,
+                alignments,
+                rows,
+            });
+            spans.push(source.span(i, end, children));
+            continue;
+        }
+
+        // link definition, in place of a paragraph
+        if let Some((label, destination, title)) = link_definition(line) {
+            state
+                .links
+                .entry(label_key(label))
+                .or_insert((destination, title));
+            continue;
+        }
+
+        // paragraph, continues until a blank line or the start of another block
+        let start = tail((i, line), line.trim_start()).0;
+        let mut text = MappedText::from_line(source, (start, line.trim_start()));
+        let mut setext = None;
+        while let Some(&(j, next)) = lines.peek() {
+            if state.commonmark()
+                && let Some(level) = setext_underline(next)
+            {
+                setext = Some((level, j + next.len()));
+                lines.next();
+                break;
+            }
+            if next.trim().is_empty() || starts_block(next, state) {
+                break;
+            }
+            text.push_line(tail((j, next), next.trim_start()));
+            lines.next();
+        }
+        text.text.truncate(text.text.trim_end().len());
+        let end = text.source_offset(text.text.len());
+        let (content, children) = parse_inlines(&text, state)?;
+        match setext {
+            Some((level, end)) => {
+                blocks.push(Block::Heading {
+                    level,
+                    content,
+                    id: String::new(),
+                });
+                spans.push(source.span(start, end, children));
+            }
+            None => {
+                blocks.push(Block::Paragraph(content));
+                spans.push(source.span(start, end, children));
+            }
+        }
+    }
+
+    Ok((blocks, spans))
+}
+
+/// The part of the line from `rest`, which must be a suffix of it.
+fn tail<'a>((offset, line): Line<'a>, rest: &'a str) -> Line<'a> {
+    (offset + line.len() - rest.len(), rest)
+}
+
+/// The line without leading and trailing whitespace.
+fn trim(line: Line) -> Line {
+    let (offset, rest) = tail(line, line.1.trim_start());
+    (offset, rest.trim_end())
+}
+
+/// A list item marker found at the start of a line.
+struct ListMarker {
+    kind: ListKind,
+    /// Byte offset of the item content in the line.
+    content: usize,
+}
+
+#[derive(PartialEq)]
+enum ListKind {
+    /// `-`, `*` or `+`
+    Bullet(char),
+    /// A number followed by `.` or `)`
+    Ordered { delimiter: char, start: u64 },
+}
+
+impl ListKind {
+    /// Whether an item with this marker continues a list started with `other`.
+    fn continues(&self, other: &ListKind) -> bool {
+        match (self, other) {
+            (Self::Bullet(a), Self::Bullet(b)) => a == b,
+            (Self::Ordered { delimiter: a, .. }, Self::Ordered { delimiter: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Recognizes a list item marker preceded by at most 3 spaces of indentation and followed by a
+/// space or the end of the line. Unordered markers are `-`, `*` and `+`, ordered markers are up to
+/// 9 digits followed by `.` or `)`.
+fn list_marker(line: &str) -> Option<ListMarker> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &line[indent..];
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+
+    let (kind, marker_len) = if digits == 0 {
+        let bullet = rest
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '-' | '*' | '+'))?;
+        (ListKind::Bullet(bullet), 1)
+    } else if digits <= 9 {
+        let delimiter = rest[digits..]
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '.' | ')'))?;
+        let start = rest[..digits].parse().ok()?;
+        (ListKind::Ordered { delimiter, start }, digits + 1)
+    } else {
+        return None;
+    };
+
+    let after = &rest[marker_len..];
+    let mut chars = after.chars().peekable();
+    let spaces = after.len() - after.trim_start_matches(' ').len();
+
+    let content = if after.trim().is_empty() {
+        // empty item
+        line.len()
+    } else if chars.parse_token(' ') {
+        // more than 4 spaces after the marker means indented content, only one belongs to it
+        indent + marker_len + if spaces > 4 { 1 } else { spaces }
+    } else {
+        return None;
+    };
+
+    Some(ListMarker { kind, content })
+}
+
+/// Returns the byte offset of the rest of the line after a quote marker `>` and one optional
+/// space, if the line is part of a quote.
+fn quote_content(line: &str) -> Option<usize> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = line[indent..].strip_prefix('>')?;
+    Some(line.len() - rest.strip_prefix(' ').unwrap_or(rest).len())
+}
+
+/// Whether the line under a possible table header looks like a delimiter row. Rows that look like
+/// one but are invalid are still accepted here, so they can be reported as errors.
+fn is_delimiter_row(header: &str, line: &str) -> bool {
+    let trimmed = line.trim();
+    let only_delimiters = trimmed.contains('-')
+        && trimmed.contains('|')
+        && trimmed
+            .chars()
+            .all(|c| matches!(c, '|' | ':' | '-' | ' ' | '\t'));
+    let pipe_framed = header.trim_start().starts_with('|')
+        && trimmed
+            .strip_prefix('|')
+            .is_some_and(|rest| rest.trim_start().starts_with(['-', ':']));
+    only_delimiters || pipe_framed
+}
+
+/// Column alignments of a table with the given header and delimiter rows, `None` if the rows
+/// do not make a valid table and are read as a paragraph.
+fn table_alignments(
+    source: &Source,
+    header: Line,
+    delimiter: Line,
+    state: &mut ParseState,
+) -> Result<Option<Vec<Alignment>>, MdParseError> {
+    let alignments = match parse_delimiter_row(source, delimiter) {
+        Ok(alignments) => alignments,
+        Err(e) => {
+            state.report(e)?;
+            return Ok(None);
+        }
+    };
+    let header_cells = split_row(header).len();
+    if header_cells != alignments.len() {
+        state.report(
+            MdParseError::new(
+                format!("{header_cells} delimiter cells"),
+                format!("{} delimiter cells", alignments.len()),
+            )
+            .at(source.position(delimiter.0)),
+        )?;
+        return Ok(None);
+    }
+    Ok(Some(alignments))
+}
+
+/// Parses column alignments from a table delimiter row like `| :--- | :---: | ---: |`.
+fn parse_delimiter_row(source: &Source, line: Line) -> Result<Vec<Alignment>, MdParseError> {
+    split_row(line)
+        .into_iter()
+        .map(|(offset, cell)| {
+            let left = cell.starts_with(':');
+            let right = cell.len() > 1 && cell.ends_with(':');
+            let dashes = &cell[usize::from(left)..cell.len() - usize::from(right)];
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return Err(MdParseError::new(
+                    "a delimiter cell like `---`, `:---`, `---:` or `:---:`",
+                    cell,
+                )
+                .at(source.position(offset)));
+            }
+            Ok(match (left, right) {
+                (true, true) => Alignment::Center,
+                (true, false) => Alignment::Left,
+                (false, true) => Alignment::Right,
+                (false, false) => Alignment::None,
+            })
+        })
+        .collect()
+}
+
+/// Splits a table row into trimmed cells on unescaped pipes, ignoring the outer pipes.
+fn split_row(line: Line) -> Vec<Line> {
+    let (offset, line) = trim(line);
+    let (offset, line) = tail((offset, line), line.strip_prefix('|').unwrap_or(line));
+    let mut cells = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '|' if !escaped => {
+                cells.push(trim((offset + start, &line[start..i])));
+                start = i + 1;
+            }
+            _ => escaped = false,
+        }
+    }
+    if start < line.len() {
+        cells.push(trim((offset + start, &line[start..])));
+    }
+    cells
+}
+
+/// Parses the inline content of the cells of a table row, padding or cutting the row to the
+/// column count. Added cells get an empty span at the end of the row.
+fn parse_row(
+    source: &Source,
+    line: Line,
+    columns: usize,
+    state: &mut ParseState,
+) -> Result<(Vec<Vec<Inline>>, Vec<SpanTree>), MdParseError> {
+    let end = line.0 + line.1.len();
+    let mut cells = Vec::new();
+    let mut spans = Vec::new();
+    for cell in split_row(line).into_iter().take(columns) {
+        let (content, children) = parse_inlines(&MappedText::from_line(source, cell), state)?;
+        cells.push(content);
+        spans.push(source.span(cell.0, cell.0 + cell.1.len(), children));
+    }
+    while cells.len() < columns {
+        cells.push(Vec::new());
+        spans.push(source.span(end, end, Vec::new()));
+    }
+    Ok((cells, spans))
+}
+
+/// Splits the `[ ]` or `[x]` task marker off the first line of a list item, followed by
+/// whitespace or the end of the line. Returns whether the task is done, `None` if there is no
+/// marker.
+fn task_marker(line: Line) -> (Option<bool>, Line) {
+    let text = line.1.trim_start();
+    let checked = match text.get(..3) {
+        Some("[ ]") => false,
+        Some("[x]" | "[X]") => true,
+        _ => return (None, line),
+    };
+    let rest = &text[3..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return (None, line);
+    }
+    (Some(checked), tail(line, rest.trim_start()))
+}
+
+/// Whether the line opens a block that interrupts a paragraph.
+fn starts_block(line: &str, state: &ParseState) -> bool {
+    let trimmed = line.trim_start();
+    // only lists starting from 1 may interrupt, so numbers in wrapped prose stay text
+    // and in CommonMark only items that are not empty
+    let list = list_marker(line).is_some_and(|m| {
+        let empty = m.content == line.len();
+        match m.kind {
+            ListKind::Bullet(_) => !(empty && state.commonmark()),
+            ListKind::Ordered { start, .. } => start == 1 && !(empty && state.commonmark()),
+        }
+    });
+    if state.commonmark() {
+        return indent_width(line) < 4
+            && (atx_heading((0, line)).is_some()
+                || code_fence(line).is_some()
+                || is_thematic_break(line)
+                || list
+                || quote_content(line).is_some());
+    }
+    trimmed.starts_with('#')
+        || trimmed.starts_with("```")
+        || list
+        || quote_content(line).is_some()
+        || footnote_definition(line).is_some()
+}
+
+/// The label, destination and title of a `[label]: destination "title"` link definition. The
+/// destination may be wrapped in `<>`, the title quoted with `"`, `'` or wrapped in `()`.
+fn link_definition(line: &str) -> Option<(&str, String, Option<String>)> {
+    if indent_width(line) >= 4 {
+        return None;
+    }
+    let rest = line.trim_start().strip_prefix('[')?;
+    let close = rest.find(']')?;
+    let label = &rest[..close];
+    if label.trim().is_empty() || label.starts_with('^') || label.contains('[') {
+        return None;
+    }
+    let rest = rest[close + 1..].strip_prefix(':')?.trim_start();
+    let (destination, rest) = match rest.strip_prefix('<') {
+        Some(rest) => {
+            let close = rest.find('>')?;
+            (&rest[..close], &rest[close + 1..])
+        }
+        None => {
+            let close = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if close == 0 {
+                // only `<>` makes an empty destination
+                return None;
+            }
+            (&rest[..close], &rest[close..])
+        }
+    };
+
+    let title = rest.trim();
+    if title.is_empty() {
+        return Some((label, destination.to_string(), None));
+    }
+    // the title is separated from the destination by whitespace
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let close = match title.chars().next()? {
+        '"' => '"',
+        '\'' => '\'',
+        '(' => ')',
+        _ => return None,
+    };
+    let title = title[1..].strip_suffix(close)?;
+    Some((label, destination.to_string(), Some(title.to_string())))
+}
+
+/// The label of the footnote the line defines, if it starts with `[^label]:`.
+fn footnote_definition(line: &str) -> Option<&str> {
+    footnote_label(line).filter(|label| line[label.len() + 3..].starts_with(':'))
+}
+
+/// A block with its span children.
+type SpannedBlock = (Block, Vec<SpanTree>);
+
+/// Parses the CommonMark leaf blocks that start at the line: indented and fenced code, ATX
+/// headings and thematic breaks. Returns the block and the offset where it ends.
+fn commonmark_leaf<'a>(
+    source: &Source,
+    line: Line<'a>,
+    lines: &mut Peekable<impl Iterator<Item = Line<'a>>>,
+    state: &mut ParseState,
+) -> Result<Option<(SpannedBlock, usize)>, MdParseError> {
+    let (i, text) = line;
+    let mut end = i + text.len();
+
+    // indented code, up to the last line that is not blank
+    if indent_width(text) >= 4 {
+        let mut code_lines = vec![strip_indent(text, 4)];
+        let mut kept = 1;
+        while let Some(&(j, next)) = lines.peek() {
+            if indent_width(next) < 4 && !next.trim().is_empty() {
+                break;
+            }
+            code_lines.push(strip_indent(next, 4));
+            if !next.trim().is_empty() {
+                kept = code_lines.len();
+                end = j + next.len();
+            }
+            lines.next();
+        }
+        let content = code_lines[..kept]
+            .iter()
+            .map(|l| format!("{l}\n"))
+            .collect();
+        let code = Block::Code {
+            language: None,
+            content,
+            attributes: BTreeMap::new(),
+        };
+        return Ok(Some(((code, Vec::new()), end)));
+    }
+
+    if is_thematic_break(text) {
+        return Ok(Some(((Block::ThematicBreak, Vec::new()), end)));
+    }
+
+    if let Some((level, content)) = atx_heading(line) {
+        let (content, children) = parse_inlines(&MappedText::from_line(source, content), state)?;
+        let heading = Block::Heading {
+            level,
+            content,
+            id: String::new(),
+        };
+        return Ok(Some(((heading, children), end)));
+    }
+
+    // fenced code, up to a closing fence at least as long as the opening one or the end
+    if let Some(fence) = code_fence(text) {
+        let mut content = String::new();
+        for (j, next) in lines.by_ref() {
+            end = j + next.len();
+            if fence.closed_by(next) {
+                break;
+            }
+            // the indentation of the opening fence is removed from the content
+            let indent = next.len() - next.trim_start_matches(' ').len();
+            content.push_str(&next[indent.min(fence.indent)..]);
+            content.push('\n');
+        }
+        let (language, attributes) = info_string(source, tail(line, fence.info), state)?;
+        let code = Block::Code {
+            language,
+            content,
+            attributes,
+        };
+        return Ok(Some(((code, Vec::new()), end)));
+    }
+
+    Ok(None)
+}
+
+/// Splits a fence info string like `rust title="main.rs" linenos` into the language and the
+/// attributes after it. Values may be quoted with `"` or `'`, bare keys get an empty value.
+/// Unclosed quotes, whose values run to the end, and invalid `hl_lines` are reported.
+fn info_string(
+    source: &Source,
+    (offset, info): Line,
+    state: &mut ParseState,
+) -> Result<(Option<String>, BTreeMap<String, String>), MdParseError> {
+    let mut language = None;
+    let mut attributes = BTreeMap::new();
+    let mut rest = info.trim_start();
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = &rest[key_end..];
+        let value = match rest.strip_prefix('=') {
+            Some(after) if after.starts_with(['"', '\'']) => {
+                let quoted = &after[1..];
+                let quote = &after[..1];
+                if let Some(close) = quoted.find(quote) {
+                    rest = &quoted[close + 1..];
+                    &quoted[..close]
                 } else {
-                    code.push_str(line);
-                    code.push('\n');
+                    let at = offset + info.len() - after.len();
+                    state.report(
+                        MdParseError::new(format!("closing `{quote}`"), "end of line")
+                            .at(source.position(at)),
+                    )?;
+                    rest = "";
+                    quoted
                 }
             }
-            if successful {
+            Some(after) => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                rest = &after[end..];
+                &after[..end]
+            }
+            // the first word is the language
+            None if language.is_none() && attributes.is_empty() => {
+                language = Some(key.to_string());
+                rest = rest.trim_start();
                 continue;
             }
-            Err(MdParseError::from_line(i + 1, "a terminating '```'", ""))?;
+            None => "",
+        };
+        if !key.is_empty() {
+            attributes.insert(key.to_string(), value.to_string());
         }
+        rest = rest.trim_start();
+    }
+
+    if let Some(lines) = attributes.get("hl_lines")
+        && parse_line_ranges(lines).is_none()
+    {
+        let at = offset + info.find("hl_lines").unwrap_or(0);
+        state.report(
+            MdParseError::new("line numbers like `1 3-5` in `hl_lines`", lines)
+                .at(source.position(at)),
+        )?;
+        attributes.remove("hl_lines");
+    }
+    Ok((language, attributes))
+}
+
+/// Whether the line is a CommonMark thematic break: three or more `*`, `-` or `_`, optionally
+/// separated by spaces.
+fn is_thematic_break(line: &str) -> bool {
+    let rest = line.trim();
+    let Some(c) = rest.chars().next().filter(|c| matches!(c, '*' | '-' | '_')) else {
+        return false;
+    };
+    indent_width(line) < 4
+        && rest.chars().filter(|&x| x == c).count() >= 3
+        && rest.chars().all(|x| x == c || x == ' ' || x == '\t')
+}
 
-        // lists TODO
+/// Recognizes a CommonMark ATX heading: 1 to 6 `#` followed by a space or the end of the line.
+/// Returns the level and the content without the optional closing `#`s.
+fn atx_heading(line: Line) -> Option<(u8, Line)> {
+    let (offset, text) = line;
+    let indent = text.len() - text.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &text[indent..];
+    let level = rest.len() - rest.trim_start_matches('#').len();
+    let after = &rest[level..];
+    if !(1..=6).contains(&level) || !(after.is_empty() || after.starts_with([' ', '\t'])) {
+        return None;
+    }
+    let mut content = after.trim_matches([' ', '\t']);
+    let unclosed = content.trim_end_matches('#');
+    if unclosed.is_empty() {
+        content = "";
+    } else if unclosed.ends_with([' ', '\t']) {
+        content = unclosed.trim_end_matches([' ', '\t']);
+    }
+    let start = offset + text.len() - after.trim_start_matches([' ', '\t']).len();
+    Some((u8::try_from(level).ok()?, (start, content)))
+}
 
-        // paragraph
-        blocks.push(Block::Paragraph(
-            parse_inlines(line).map_err(|e| e.set_line(i + 1))?,
-        ));
+/// The level of the heading a CommonMark setext underline makes of the paragraph above it:
+/// 1 for `=`, 2 for `-`.
+fn setext_underline(line: &str) -> Option<u8> {
+    let rest = line.trim();
+    let underline = rest.chars().next()?;
+    let level = match underline {
+        '=' => 1,
+        '-' => 2,
+        _ => return None,
+    };
+    (indent_width(line) < 4 && rest.chars().all(|c| c == underline)).then_some(level)
+}
+
+/// The opening fence of a CommonMark fenced code block.
+struct Fence<'a> {
+    /// Spaces before the fence, removed from the lines of code.
+    indent: usize,
+    /// `` ` `` or `~`
+    char: char,
+    len: usize,
+    /// The rest of the line, the info string with the language and attributes of the code.
+    info: &'a str,
+}
+
+impl Fence<'_> {
+    /// Whether the line closes the code block this fence opened.
+    fn closed_by(&self, line: &str) -> bool {
+        let rest = line.trim_start_matches(' ');
+        let len = rest.len() - rest.trim_start_matches(self.char).len();
+        line.len() - rest.len() < 4 && len >= self.len && rest[len..].trim().is_empty()
     }
+}
 
-    Ok(blocks)
+/// Recognizes an opening CommonMark code fence, three or more `` ` `` or `~`.
+fn code_fence(line: &str) -> Option<Fence<'_>> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &line[indent..];
+    let char = rest.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = rest.len() - rest.trim_start_matches(char).len();
+    let info = &rest[len..];
+    // backticks in the info string would make an inline code span
+    if len < 3 || (char == '`' && info.contains('`')) {
+        return None;
+    }
+    Some(Fence {
+        indent,
+        char,
+        len,
+        info,
+    })
+}
+
+/// Width of the leading whitespace, tabs counted to the next multiple of 4.
+fn indent_width(line: &str) -> usize {
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += 4 - width % 4,
+            _ => break,
+        }
+    }
+    width
+}
+
+/// Removes leading whitespace from the line up to the given width.
+fn strip_indent(line: &str, width: usize) -> &str {
+    let mut stripped = 0;
+    for (i, c) in line.char_indices() {
+        if stripped >= width {
+            return &line[i..];
+        }
+        match c {
+            ' ' => stripped += 1,
+            '\t' => stripped += 4 - stripped % 4,
+            _ => return &line[i..],
+        }
+    }
+    ""
 }
 
 /*
@@ -0,0 +1,197 @@
+//! Footnotes: `[^label]` references and `[^label]: text` definitions. Both are collected while
+//! parsing, and the definitions end up numbered in a section at the end of the document.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{ParseState, source::Source};
+use crate::{
+    MdParseError,
+    ast::{Block, Footnote, Inline, Span, SpanTree},
+};
+
+/// A footnote definition, taken out of the blocks it was written between.
+pub struct Definition {
+    pub label: String,
+    pub blocks: Vec<Block>,
+    pub span: SpanTree,
+}
+
+/// The footnote definitions and references of the document, in source order.
+#[derive(Default)]
+pub struct Footnotes {
+    pub definitions: Vec<Definition>,
+    /// Labels of the references with their offsets in the source.
+    pub references: Vec<(String, usize)>,
+}
+
+/// The label of the `[^label]` the text starts with. Labels are not empty and have no
+/// whitespace or brackets.
+pub fn footnote_label(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix("[^")?;
+    let label = &rest[..rest.find(']')?];
+    let valid = !label.is_empty() && !label.contains(|c: char| c.is_whitespace() || c == '[');
+    valid.then_some(label)
+}
+
+/// Labels match regardless of case.
+fn key(label: &str) -> String {
+    label.to_lowercase()
+}
+
+/// Numbers the footnotes in the order of their first references, fills in the references and
+/// appends the footnotes to the blocks as a [`Block::Footnotes`]. References without a
+/// definition are read as text and definitions without a reference are dropped, both reported.
+pub fn resolve_footnotes(
+    blocks: &mut Vec<Block>,
+    spans: &mut Vec<SpanTree>,
+    source: &Source,
+    state: &mut ParseState,
+) -> Result<(), MdParseError> {
+    let Footnotes {
+        definitions,
+        references,
+    } = std::mem::take(&mut state.footnotes);
+
+    // the first definition of a label counts
+    let mut defined = HashSet::new();
+    let mut definitions: Vec<_> = definitions.into_iter().map(Some).collect();
+    for definition in &mut definitions {
+        let Some(d) = definition else { continue };
+        if !defined.insert(key(&d.label)) {
+            state.report(
+                MdParseError::new(
+                    format!("a single definition of `[^{}]`", d.label),
+                    "another definition",
+                )
+                .at(d.span.span.start),
+            )?;
+            *definition = None;
+        }
+    }
+
+    let mut numbers = HashMap::new();
+    for (label, offset) in references {
+        if !defined.contains(&key(&label)) {
+            state.report(
+                MdParseError::new(format!("a definition of `[^{label}]`"), "no definition")
+                    .at(source.position(offset)),
+            )?;
+        } else if !numbers.contains_key(&key(&label)) {
+            numbers.insert(key(&label), numbers.len() + 1);
+        }
+    }
+    for d in definitions.iter().flatten() {
+        if !numbers.contains_key(&key(&d.label)) {
+            state.report(
+                MdParseError::new(format!("a reference to `[^{}]`", d.label), "no reference")
+                    .at(d.span.span.start),
+            )?;
+        }
+    }
+
+    let mut used: Vec<_> = definitions
+        .into_iter()
+        .flatten()
+        .filter_map(|d| Some((*numbers.get(&key(&d.label))?, d)))
+        .collect();
+    used.sort_by_key(|(number, _)| *number);
+
+    let mut occurrences = HashMap::new();
+    number_references(blocks, &numbers, &mut occurrences);
+    for (_, d) in &mut used {
+        number_references(&mut d.blocks, &numbers, &mut occurrences);
+    }
+    if used.is_empty() {
+        return Ok(());
+    }
+
+    let span = Span {
+        start: used
+            .iter()
+            .map(|(_, d)| d.span.span.start)
+            .min()
+            .unwrap_or_default(),
+        end: used
+            .iter()
+            .map(|(_, d)| d.span.span.end)
+            .max()
+            .unwrap_or_default(),
+    };
+    let (footnotes, children) = used
+        .into_iter()
+        .map(|(number, d)| {
+            let footnote = Footnote {
+                label: d.label,
+                number,
+                blocks: d.blocks,
+                references: occurrences.get(&number).copied().unwrap_or_default(),
+            };
+            (footnote, d.span)
+        })
+        .unzip();
+    blocks.push(Block::Footnotes(footnotes));
+    spans.push(SpanTree { span, children });
+    Ok(())
+}
+
+/// Gives the footnote references their numbers and occurrences, or turns them back into text
+/// if the footnote is not defined.
+fn number_references(
+    blocks: &mut [Block],
+    numbers: &HashMap<String, usize>,
+    occurrences: &mut HashMap<usize, usize>,
+) {
+    for block in blocks {
+        match block {
+            Block::Paragraph(content) | Block::Heading { content, .. } => {
+                number_inline_references(content, numbers, occurrences);
+            }
+            Block::Quote(blocks) => number_references(blocks, numbers, occurrences),
+            Block::List(items) | Block::OrderedList { items, .. } => {
+                for item in items {
+                    number_references(&mut item.blocks, numbers, occurrences);
+                }
+            }
+            Block::Table { header, rows, .. } => {
+                for cell in header.iter_mut().chain(rows.iter_mut().flatten()) {
+                    number_inline_references(cell, numbers, occurrences);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn number_inline_references(
+    inlines: &mut [Inline],
+    numbers: &HashMap<String, usize>,
+    occurrences: &mut HashMap<usize, usize>,
+) {
+    for inline in inlines {
+        match inline {
+            Inline::Bold(content)
+            | Inline::Italic(content)
+            | Inline::Strikethrough(content)
+            | Inline::Highlight(content)
+            | Inline::Subscript(content)
+            | Inline::Superscript(content)
+            | Inline::Link { text: content, .. } => {
+                number_inline_references(content, numbers, occurrences);
+            }
+            Inline::FootnoteRef {
+                label,
+                number,
+                occurrence,
+            } => match numbers.get(&key(label)) {
+                Some(&n) => {
+                    let count = occurrences.entry(n).or_default();
+                    *count += 1;
+                    *number = n;
+                    *occurrence = *count;
+                }
+                None => *inline = Inline::Text(format!("[^{label}]")),
+            },
+            _ => {}
+        }
+    }
+}
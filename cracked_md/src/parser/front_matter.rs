@@ -0,0 +1,345 @@
+//! Front matter at the top of a page: `+++` delimits a TOML subset, `---` a `key: value` subset.
+
+use crate::{
+    MdParseError,
+    ast::{Date, Metadata, Value},
+};
+
+/// Parses the front matter if the first line opens one. Returns the metadata and the number of
+/// lines it took, including the delimiters.
+pub fn parse_front_matter(lines: &[(usize, &str)]) -> Result<(Metadata, usize), MdParseError> {
+    let Some(&(first, delimiter)) = lines.first() else {
+        return Ok((Metadata::default(), 0));
+    };
+    let delimiter = delimiter.trim_end();
+    if delimiter != "+++" && delimiter != "---" {
+        return Ok((Metadata::default(), 0));
+    }
+
+    let len = front_matter_len(lines);
+    if len == 0 {
+        Err(MdParseError::from_line(
+            first + 1,
+            format!("front matter closed by `{delimiter}`"),
+            "end of file",
+        )
+        .set_col(1))?;
+    }
+    let body = &lines[1..len - 1];
+
+    let metadata = if delimiter == "+++" {
+        parse_toml(body)?
+    } else {
+        parse_key_values(body)?
+    };
+    Ok((metadata, len))
+}
+
+/// Number of lines the front matter takes, including the delimiters. Zero if there is none or it
+/// is not closed.
+fn front_matter_len(lines: &[(usize, &str)]) -> usize {
+    let Some((_, delimiter)) = lines.first() else {
+        return 0;
+    };
+    let delimiter = delimiter.trim_end();
+    if delimiter != "+++" && delimiter != "---" {
+        return 0;
+    }
+    lines
+        .iter()
+        .skip(1)
+        .position(|(_i, l)| l.trim_end() == delimiter)
+        .map_or(0, |end| end + 2)
+}
+
+/// `key = value` lines with TOML strings, integers, booleans, dates and arrays.
+pub fn parse_toml(lines: &[(usize, &str)]) -> Result<Metadata, MdParseError> {
+    let mut metadata = Metadata::default();
+    for &(i, line) in lines {
+        let content = line.trim_start();
+        if content.trim().is_empty() || content.starts_with('#') {
+            continue;
+        }
+        let at_key = |e: MdParseError| e.set_line(i + 1).set_col(col(line, content));
+        let (key, value) = content
+            .split_once('=')
+            .ok_or_else(|| at_key(MdParseError::new("`key = value`", content.trim())))?;
+        let key = parse_key(key.trim()).map_err(at_key)?;
+        let (value, rest) = toml_value(line, value).map_err(|e| e.set_line(i + 1))?;
+        let rest = rest.trim_start();
+        if !rest.trim().is_empty() && !rest.starts_with('#') {
+            Err(
+                MdParseError::from_line(i + 1, "end of line", rest.trim()).set_col(col(line, rest))
+            )?;
+        }
+        insert_unique(&mut metadata, key, value).map_err(at_key)?;
+    }
+    Ok(metadata)
+}
+
+/// Column of `rest`, the end of `line`, counted in characters from 1.
+fn col(line: &str, rest: &str) -> usize {
+    line[..line.len() - rest.len()].chars().count() + 1
+}
+
+/// Parses a TOML value from `input`, the end of `line`, returning the rest. Errors point at
+/// the column of the value.
+fn toml_value<'a>(line: &str, input: &'a str) -> Result<(Value, &'a str), MdParseError> {
+    let input = input.trim_start();
+    let at_value = |e: MdParseError| e.set_col(col(line, input));
+    if let Some(rest) = input.strip_prefix('"') {
+        let (s, rest) = basic_string(rest).map_err(at_value)?;
+        Ok((Value::String(s), rest))
+    } else if let Some(rest) = input.strip_prefix('\'') {
+        let (s, rest) = rest
+            .split_once('\'')
+            .ok_or_else(|| at_value(MdParseError::new("closing `'`", "end of line")))?;
+        Ok((Value::String(s.to_string()), rest))
+    } else if let Some(mut rest) = input.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            let (value, after) = toml_value(line, rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                Err(MdParseError::new("`,` or `]`", rest).set_col(col(line, rest)))?;
+            }
+        }
+    } else {
+        let end = input
+            .find(|c: char| c == ',' || c == ']' || c == '#' || c.is_whitespace())
+            .unwrap_or(input.len());
+        let token = &input[..end];
+        let value = scalar(&token.replace('_', "")).ok_or_else(|| {
+            at_value(MdParseError::new(
+                "a string, integer, boolean, date or array",
+                token,
+            ))
+        })?;
+        Ok((value, &input[end..]))
+    }
+}
+
+/// Parses the rest of a double quoted string with escapes, returning the rest after the quote.
+fn basic_string(input: &str) -> Result<(String, &str), MdParseError> {
+    let mut s = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((s, &input[i + 1..])),
+            '\\' => match chars.next().map(|(_i, c)| c) {
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                Some(c @ ('"' | '\\')) => s.push(c),
+                got => Err(MdParseError::new(
+                    "an escape sequence",
+                    got.map(|c| format!("\\{c}")).unwrap_or_default(),
+                ))?,
+            },
+            _ => s.push(c),
+        }
+    }
+    Err(MdParseError::new("closing `\"`", "end of line"))
+}
+
+/// `key: value` lines, with arrays written as `[a, b]` or as `- item` lines under the key.
+fn parse_key_values(lines: &[(usize, &str)]) -> Result<Metadata, MdParseError> {
+    let mut metadata = Metadata::default();
+    let mut lines = lines.iter().peekable();
+    while let Some(&(i, line)) = lines.next() {
+        let content = line.trim_start();
+        if content.trim().is_empty() || content.starts_with('#') {
+            continue;
+        }
+        let at_key = |e: MdParseError| e.set_line(i + 1).set_col(col(line, content));
+        let (key, value) = content
+            .split_once(':')
+            .ok_or_else(|| at_key(MdParseError::new("`key: value`", content.trim())))?;
+        let key = parse_key(key.trim()).map_err(at_key)?;
+        let value_start = value.trim_start();
+        let at_value = |e: MdParseError| e.set_line(i + 1).set_col(col(line, value_start));
+        let value = value_start.trim_end();
+
+        let value = if value.is_empty() {
+            let mut items = Vec::new();
+            while let Some(&&(j, item_line)) = lines.peek() {
+                let Some(item) = item_line.trim_start().strip_prefix('-') else {
+                    break;
+                };
+                let item = item.trim_start();
+                items.push(
+                    key_value_scalar(item.trim_end())
+                        .map_err(|e| e.set_line(j + 1).set_col(col(item_line, item)))?,
+                );
+                lines.next();
+            }
+            if items.is_empty() {
+                Err(at_value(MdParseError::new("a value", "nothing")))?;
+            }
+            Value::Array(items)
+        } else if let Some(inner) = value.strip_prefix('[') {
+            let inner = inner
+                .strip_suffix(']')
+                .ok_or_else(|| at_value(MdParseError::new("closing `]`", value)))?;
+            let items = inner
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(key_value_scalar)
+                .collect::<Result<_, _>>()
+                .map_err(at_value)?;
+            Value::Array(items)
+        } else {
+            key_value_scalar(value).map_err(at_value)?
+        };
+        insert_unique(&mut metadata, key, value).map_err(at_key)?;
+    }
+    Ok(metadata)
+}
+
+/// A quoted string or a scalar, with unquoted text taken as a string.
+fn key_value_scalar(value: &str) -> Result<Value, MdParseError> {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value.strip_prefix(quote) {
+            let inner = inner
+                .strip_suffix(quote)
+                .ok_or(MdParseError::new(format!("closing `{quote}`"), value))?;
+            return Ok(Value::String(inner.to_string()));
+        }
+    }
+    Ok(scalar(value).unwrap_or(Value::String(value.to_string())))
+}
+
+/// A boolean, integer or `YYYY-MM-DD` date.
+fn scalar(token: &str) -> Option<Value> {
+    match token {
+        "true" => Some(Value::Boolean(true)),
+        "false" => Some(Value::Boolean(false)),
+        _ => token
+            .parse()
+            .ok()
+            .map(Value::Integer)
+            .or_else(|| Date::parse(token).map(Value::Date)),
+    }
+}
+
+/// A bare key of letters, digits, `_` and `-`, or a quoted key.
+fn parse_key(key: &str) -> Result<String, MdParseError> {
+    let unquoted = key
+        .strip_prefix('"')
+        .and_then(|k| k.strip_suffix('"'))
+        .or_else(|| key.strip_prefix('\'').and_then(|k| k.strip_suffix('\'')));
+    if let Some(k) = unquoted {
+        return Ok(k.to_string());
+    }
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        Err(MdParseError::new("a key", key))?;
+    }
+    Ok(key.to_string())
+}
+
+fn insert_unique(metadata: &mut Metadata, key: String, value: Value) -> Result<(), MdParseError> {
+    if metadata.get(&key).is_some() {
+        Err(MdParseError::new("a unique key", format!("`{key}` again")))?;
+    }
+    metadata.insert(key, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_front_matter;
+    use crate::ast::{Date, Value};
+
+    fn lines(s: &str) -> Vec<(usize, &str)> {
+        s.lines().enumerate().collect()
+    }
+
+    #[test]
+    fn no_front_matter() {
+        let (metadata, len) = parse_front_matter(&lines("# Title")).unwrap();
+        assert!(metadata.is_empty());
+        assert_eq!(len, 0);
+    }
+
+    #[test]
+    fn toml_values() {
+        let md = "+++\ntitle = \"Hello \\\"world\\\"\" # comment\ncount = 1_000\ndraft = true\ndate = 2024-02-29\ntags = ['a', \"b\"]\n+++\n# Body";
+        let (metadata, len) = parse_front_matter(&lines(md)).unwrap();
+
+        assert_eq!(len, 7);
+        assert_eq!(metadata.title(), Some("Hello \"world\""));
+        assert_eq!(metadata.get("count"), Some(&Value::Integer(1000)));
+        assert!(metadata.draft());
+        assert_eq!(
+            metadata.date(),
+            Some(Date {
+                year: 2024,
+                month: 2,
+                day: 29
+            })
+        );
+        assert_eq!(metadata.tags(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn key_value_values() {
+        let md = "---\ntitle: Plain text: with colon\ndescription: \"quoted\"\nweight: -3\ntags: [rust, web]\nauthors:\n  - me\n  - you\n---";
+        let (metadata, len) = parse_front_matter(&lines(md)).unwrap();
+
+        assert_eq!(len, 9);
+        assert_eq!(metadata.title(), Some("Plain text: with colon"));
+        assert_eq!(metadata.description(), Some("quoted"));
+        assert_eq!(metadata.get("weight"), Some(&Value::Integer(-3)));
+        assert_eq!(metadata.tags(), vec!["rust", "web"]);
+        assert_eq!(
+            metadata.get("authors"),
+            Some(&Value::Array(vec![
+                Value::String("me".to_string()),
+                Value::String("you".to_string())
+            ]))
+        );
+        assert!(!metadata.draft());
+    }
+
+    #[test]
+    fn unclosed_front_matter() {
+        let err = parse_front_matter(&lines("+++\ntitle = \"a\"")).unwrap_err();
+        assert!(err.to_string().contains("on line 1"));
+    }
+
+    #[test]
+    fn invalid_toml_value_line() {
+        let err =
+            parse_front_matter(&lines("+++\ntitle = \"a\"\ndate = 2024-02-30\n+++")).unwrap_err();
+        assert!(err.to_string().contains("on line 3"));
+    }
+
+    #[test]
+    fn duplicate_key_line() {
+        let err = parse_front_matter(&lines("---\na: 1\na: 2\n---")).unwrap_err();
+        assert!(err.to_string().contains("on line 3"));
+    }
+
+    #[test]
+    fn errors_point_at_columns() {
+        let err = |md| parse_front_matter(&lines(md)).unwrap_err().to_string();
+        assert!(err("+++\n  a b = 1\n+++").contains("line 2, column 3"));
+        assert!(err("+++\nt = \"x\" y\n+++").contains("line 2, column 9"));
+        assert!(err("+++\ntags = [1, \"b\\q\"]\n+++").contains("line 2, column 12"));
+        assert!(err("+++\ntags = [1 2]\n+++").contains("line 2, column 11"));
+        assert!(err("---\ntitle: 'a\n---").contains("line 2, column 8"));
+        assert!(err("---\ntags:\n  -   \"x\n---").contains("line 3, column 7"));
+        assert!(err("---\n  a: 1\n  a: 2\n---").contains("line 3, column 3"));
+    }
+}
@@ -1,85 +1,834 @@
-use crate::{MdParseError, ast::Inline};
+use std::{collections::HashMap, ops::Range};
 
-pub fn parse_inlines(input: &str) -> Result<Vec<Inline>, MdParseError> {
+use super::{ParseState, footnote::footnote_label, label_key, source::MappedText};
+use crate::{
+    MdParseError,
+    ast::{Inline, SpanTree, plain_text},
+};
+
+/// Characters of a part of the text, with their byte offsets in the whole text.
+#[derive(Clone)]
+struct Chars<'a> {
+    inner: std::str::CharIndices<'a>,
+    base: usize,
+}
+
+impl Iterator for Chars<'_> {
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(i, c)| (self.base + i, c))
+    }
+}
+
+type Cursor<'a> = std::iter::Peekable<Chars<'a>>;
+
+fn chars(input: &str, range: Range<usize>) -> Cursor<'_> {
+    Chars {
+        inner: input[range.clone()].char_indices(),
+        base: range.start,
+    }
+    .peekable()
+}
+
+/// Offset of the next character, or `end` if there are none left.
+fn offset(chars: &mut Cursor, end: usize) -> usize {
+    chars.peek().map_or(end, |&(i, _)| i)
+}
+
+/// Parses the inline elements of the text, with their spans.
+pub fn parse_inlines(
+    text: &MappedText,
+    state: &mut ParseState,
+) -> Result<(Vec<Inline>, Vec<SpanTree>), MdParseError> {
+    if !state.inlines {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    if state.commonmark() {
+        state.emphasis = match_emphasis(&text.text, state.extensions.bare_urls);
+    }
+    parse_range(text, 0..text.text.len(), state)
+}
+
+fn parse_range(
+    text: &MappedText,
+    range: Range<usize>,
+    state: &mut ParseState,
+) -> Result<(Vec<Inline>, Vec<SpanTree>), MdParseError> {
+    let end = range.end;
     let mut inlines = Vec::new();
-    let mut chars = input.chars().peekable();
+    let mut spans = Vec::new();
+    let mut chars = chars(&text.text, range.clone());
+    // how far the elements read as text got, the ones starting before that fail for the same
+    // reason, like the `[` of an unclosed `![`, and are not reported again
+    let mut failed_until = range.start;
 
-    while let Some(c) = chars.next() {
-        match c {
-            '*' => {
-                let inner = collect_until(&mut chars, '*')?;
-                inlines.push(Inline::Bold(parse_inlines(&inner)?));
+    while let Some(&(from, _)) = chars.peek() {
+        let before = chars.clone();
+        match parse_element(text, &mut chars, end, state) {
+            Ok(Some((inline, children))) => {
+                let span = text.span(from, offset(&mut chars, end), children);
+                push_inline(&mut inlines, &mut spans, inline, span);
             }
-            '_' => {
-                let inner = collect_until(&mut chars, '_')?;
-                inlines.push(Inline::Italic(parse_inlines(&inner)?));
+            Ok(None) => parse_text(text, &mut chars, end, state, &mut inlines, &mut spans),
+            Err(e) => {
+                // an element that does not close is text, starting with its opening character,
+                // which CommonMark allows without a warning
+                if !state.commonmark() && from >= failed_until {
+                    state.report(e)?;
+                }
+                failed_until = failed_until.max(offset(&mut chars, end));
+                chars = before;
+                parse_text(text, &mut chars, end, state, &mut inlines, &mut spans);
             }
-            '`' => {
-                let code = collect_until(&mut chars, '`')?;
-                inlines.push(Inline::Code(code));
+        }
+    }
+
+    Ok((inlines, spans))
+}
+
+/// Parses the element starting at the next character, `None` if it is text.
+fn parse_element(
+    text: &MappedText,
+    chars: &mut Cursor,
+    end: usize,
+    state: &mut ParseState,
+) -> Result<Option<(Inline, Vec<SpanTree>)>, MdParseError> {
+    let Some(&(from, c)) = chars.peek() else {
+        return Ok(None);
+    };
+    let element = match c {
+        '~' | '=' | '^' => return extension(text, chars, end, state),
+        '[' if state.extensions.kbd
+            && let Some(key) = kbd(&text.text[from..end]) =>
+        {
+            advance(chars, from + key.len() + 4);
+            (Inline::Kbd(key.to_string()), Vec::new())
+        }
+        '*' | '_' if state.commonmark() => return emphasis(text, chars, end, state),
+        '`' if state.commonmark() => return Ok(Some((code_span(text, chars, end), Vec::new()))),
+        '*' | '_' => {
+            chars.next();
+            let inner = collect_until(chars, c).map_err(|e| e.at(text.position(from)))?;
+            let (content, children) = parse_range(text, inner, state)?;
+            if c == '*' {
+                (Inline::Bold(content), children)
+            } else {
+                (Inline::Italic(content), children)
             }
-            '[' => {
-                let text = collect_until(&mut chars, ']')?;
-                if let Some('(') = chars.next() {
-                    let href = collect_until(&mut chars, ')')?;
-                    inlines.push(Inline::Link {
-                        text: parse_inlines(&text)?,
-                        href,
-                    });
-                } else {
-                    Err(MdParseError::new(
-                        "(<href>)",
-                        chars.next().unwrap_or_default(),
-                    ))?;
+        }
+        '`' => {
+            chars.next();
+            let code = collect_until(chars, '`').map_err(|e| e.at(text.position(from)))?;
+            (Inline::Code(text.text[code].to_string()), Vec::new())
+        }
+        '[' if !state.commonmark() && footnote_label(&text.text[from..end]).is_some() => {
+            let label = footnote_label(&text.text[from..end]).unwrap_or_default();
+            advance(chars, from + label.len() + 3);
+            state
+                .footnotes
+                .references
+                .push((label.to_string(), text.source_offset(from)));
+            (
+                Inline::FootnoteRef {
+                    label: label.to_string(),
+                    // numbered once the whole document is parsed
+                    number: 0,
+                    occurrence: 0,
+                },
+                Vec::new(),
+            )
+        }
+        '[' => {
+            chars.next();
+            let inner = collect_label(chars, state).map_err(|e| e.at(text.position(from)))?;
+            let (href, title) = link_target(text, chars, end, inner.clone(), "(<href>)", state)?;
+            let (content, children) = parse_range(text, inner, state)?;
+            (
+                Inline::Link {
+                    text: content,
+                    href,
+                    title,
+                },
+                children,
+            )
+        }
+        '<' if let Some((url, href)) = autolink(&text.text[from..end]) => {
+            advance(chars, from + url.len() + 2);
+            let span = text.span(from + 1, from + 1 + url.len(), Vec::new());
+            (link(url, href), vec![span])
+        }
+        'h' | 'w'
+            if state.extensions.bare_urls
+                && let Some(url) = bare_url(&text.text[..end], from) =>
+        {
+            advance(chars, from + url.len());
+            let href = if url.starts_with("www.") {
+                format!("http://{url}")
+            } else {
+                url.to_string()
+            };
+            let span = text.span(from, from + url.len(), Vec::new());
+            (link(url, href), vec![span])
+        }
+        '!' if starts_image(chars) => {
+            chars.next();
+            chars.next();
+            let alt = collect_label(chars, state).map_err(|e| e.at(text.position(from)))?;
+            let (src, title) = link_target(text, chars, end, alt.clone(), "(<src>)", state)?;
+            let (alt, _) = parse_range(text, alt, state)?;
+            (
+                Inline::Image {
+                    alt: plain_text(&alt),
+                    src,
+                    title,
+                },
+                Vec::new(),
+            )
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(element))
+}
+
+/// Parses text up to the next inline element, split into text and hard line breaks.
+fn parse_text(
+    text: &MappedText,
+    chars: &mut Cursor,
+    end: usize,
+    state: &ParseState,
+    inlines: &mut Vec<Inline>,
+    spans: &mut Vec<SpanTree>,
+) {
+    let mut content = String::new();
+    let mut start = offset(chars, end);
+    // end of the text before trailing spaces or a backslash
+    let mut content_end = start;
+    let mut escaped = false;
+    let extensions = state.extensions;
+    let mut current = chars.next();
+    while let Some((i, c)) = current {
+        if c == '\\' && !escaped {
+            escaped = true;
+        } else if c == '\n' {
+            // two trailing spaces or a backslash make a hard line break
+            let hard_break = escaped || content.ends_with("  ");
+            content.truncate(content.trim_end_matches(' ').len());
+            if hard_break {
+                if !content.is_empty() {
+                    inlines.push(Inline::Text(std::mem::take(&mut content)));
+                    spans.push(text.span(start, content_end, Vec::new()));
                 }
+                inlines.push(Inline::LineBreak);
+                spans.push(text.span(content_end, i + 1, Vec::new()));
+                start = i + 1;
+            } else {
+                content.push('\n');
             }
-            _ => {
-                let mut text = String::new();
-                text.push(c);
-                let mut escaped = false;
-                while let Some(&nc) = chars.peek() {
-                    if matches!(nc, '*' | '_' | '`' | '[') && !escaped {
-                        break;
-                    }
-                    let next_c = chars.next().ok_or(MdParseError::new("a character", ""))?;
-                    if next_c == '\\' && !escaped {
-                        escaped = true;
-                    } else {
-                        escaped = false;
-                        text.push(next_c);
-                    }
-                }
-                inlines.push(Inline::Text(text));
+            content_end = i + 1;
+            escaped = false;
+        } else {
+            if escaped && state.commonmark() && !c.is_ascii_punctuation() {
+                // CommonMark only escapes punctuation, other backslashes are literal
+                content.push('\\');
+            }
+            escaped = false;
+            content.push(c);
+            if c != ' ' {
+                content_end = i + c.len_utf8();
             }
         }
+        current = match chars.peek().copied() {
+            Some((_, '*' | '_' | '`' | '[' | '<')) if !escaped => None,
+            Some((j, 'h' | 'w'))
+                if !escaped && extensions.bare_urls && bare_url(&text.text[..end], j).is_some() =>
+            {
+                None
+            }
+            Some((_, '~')) if !escaped && (extensions.strikethrough || extensions.subscript) => {
+                None
+            }
+            Some((_, '=')) if !escaped && extensions.highlight => None,
+            Some((_, '^')) if !escaped && extensions.superscript => None,
+            Some((_, '!')) if !escaped && starts_image(chars) => None,
+            _ => chars.next(),
+        };
+    }
+    if escaped {
+        // a backslash at the end of input is literal
+        content.push('\\');
+    }
+    if content.is_empty() {
+        return;
+    }
+    let span = text.span(start, offset(chars, end), Vec::new());
+    push_inline(inlines, spans, Inline::Text(content), span);
+}
+
+/// Adds an element with its span. Text right after other text, like an element read as text,
+/// continues it.
+fn push_inline(
+    inlines: &mut Vec<Inline>,
+    spans: &mut Vec<SpanTree>,
+    inline: Inline,
+    span: SpanTree,
+) {
+    if let (Some(Inline::Text(previous)), Some(previous_span), Inline::Text(content)) =
+        (inlines.last_mut(), spans.last_mut(), &inline)
+        && previous_span.span.end.offset == span.span.start.offset
+    {
+        previous.push_str(content);
+        previous_span.span.end = span.span.end;
+        return;
+    }
+    inlines.push(inline);
+    spans.push(span);
+}
+
+/// Parses the inline extensions starting with `~`, `=` or `^`, `None` if they are turned off or
+/// don't close, to be read as text.
+fn extension(
+    text: &MappedText,
+    chars: &mut Cursor,
+    end: usize,
+    state: &mut ParseState,
+) -> Result<Option<(Inline, Vec<SpanTree>)>, MdParseError> {
+    let from = offset(chars, end);
+    let rest = &text.text[from..end];
+    let extensions = state.extensions;
+    let (delimiter, single): (&str, bool) = if rest.starts_with("~~") && extensions.strikethrough {
+        ("~~", false)
+    } else if rest.starts_with("==") && extensions.highlight {
+        ("==", false)
+    } else if (rest.starts_with('~') && extensions.subscript)
+        || (rest.starts_with('^') && extensions.superscript)
+    {
+        (&rest[..1], true)
+    } else {
+        return Ok(None);
+    };
+
+    let start = from + delimiter.len();
+    // the first closing delimiter that is not escaped
+    let escaped = |i: usize| {
+        let before = &text.text[start..i];
+        (before.len() - before.trim_end_matches('\\').len()) % 2 == 1
+    };
+    let Some(close) = text.text[start..end]
+        .match_indices(delimiter)
+        .map(|(c, _)| start + c)
+        .find(|&c| !escaped(c))
+    else {
+        return Ok(None);
+    };
+    let content = &text.text[start..close];
+    // `~~` and `^^` are no subscript or superscript, and those have no whitespace in them
+    let edge = |c: char| c.is_whitespace() || delimiter.starts_with(c);
+    let invalid = content.is_empty()
+        || content.starts_with(edge)
+        || content.ends_with(edge)
+        || (single && content.contains(char::is_whitespace))
+        || (single && text.text[..from].ends_with(delimiter));
+    if invalid {
+        return Ok(None);
+    }
+
+    advance(chars, close + delimiter.len());
+    let (content, children) = parse_range(text, start..close, state)?;
+    let inline = match delimiter {
+        "~~" => Inline::Strikethrough(content),
+        "==" => Inline::Highlight(content),
+        "~" => Inline::Subscript(content),
+        _ => Inline::Superscript(content),
+    };
+    Ok(Some((inline, children)))
+}
+
+/// A link showing its own URL.
+fn link(url: &str, href: String) -> Inline {
+    Inline::Link {
+        text: vec![Inline::Text(url.to_string())],
+        href,
+        title: None,
+    }
+}
+
+/// The URL of the autolink the text starts with and its href: an absolute URI like
+/// `<https://example.com>` or `<mailto:me@example.com>`, or an email address like
+/// `<me@example.com>`, which links with `mailto:`. Autolinks have no whitespace in them.
+fn autolink(text: &str) -> Option<(&str, String)> {
+    let rest = text.strip_prefix('<')?;
+    let url = &rest[..rest.find(['<', '>'])?];
+    if !rest[url.len()..].starts_with('>')
+        || url.contains(|c: char| c.is_whitespace() || c.is_control())
+    {
+        return None;
     }
+    if is_uri(url) {
+        Some((url, url.to_string()))
+    } else if is_email(url) {
+        Some((url, format!("mailto:{url}")))
+    } else {
+        None
+    }
+}
+
+/// Whether the text starts with a scheme of 2 to 32 characters and a `:`.
+fn is_uri(url: &str) -> bool {
+    url.split_once(':').is_some_and(|(scheme, _)| {
+        (2..=32).contains(&scheme.len())
+            && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+fn is_email(address: &str) -> bool {
+    let Some((local, domain)) = address.split_once('@') else {
+        return false;
+    };
+    let local = !local.is_empty()
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".!#$%&'*+/=?^_`{|}~-".contains(c));
+    local
+        && domain.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// The bare URL at `from` in the text, like `https://example.com/page` or `www.example.com`.
+/// It starts a word, or follows `*`, `_`, `~` or `(`, and runs up to whitespace or `<`. Trailing
+/// punctuation is left out, like the `.` ending a sentence, a `)` without a `(` in the URL or an
+/// entity like `&amp;`, as is anything without a domain with a `.` in it.
+fn bare_url(text: &str, from: usize) -> Option<&str> {
+    let rest = &text[from..];
+    let domain_start = if rest.starts_with("www.") {
+        0
+    } else {
+        ["https://", "http://"]
+            .into_iter()
+            .find(|scheme| rest.starts_with(scheme))?
+            .len()
+    };
+    let starts_word = text[..from]
+        .chars()
+        .next_back()
+        .is_none_or(|c| c.is_whitespace() || matches!(c, '*' | '_' | '~' | '('));
+    if !starts_word {
+        return None;
+    }
+
+    let mut url = &rest[..rest
+        .find(|c: char| c.is_whitespace() || c == '<')
+        .unwrap_or(rest.len())];
+    loop {
+        let mut trimmed = url.trim_end_matches(['?', '!', '.', ',', ':', '*', '_', '~']);
+        if trimmed.ends_with(')') && trimmed.matches(')').count() > trimmed.matches('(').count() {
+            trimmed = &trimmed[..trimmed.len() - 1];
+        } else if let Some(entity) = trimmed.strip_suffix(';')
+            && let Some(amp) = entity.rfind('&')
+            && entity.len() > amp + 1
+            && entity[amp + 1..].chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            trimmed = &trimmed[..amp];
+        }
+        if trimmed.len() == url.len() {
+            break;
+        }
+        url = trimmed;
+    }
+
+    // the domain has at least two parts, with no `_` in the last two
+    let domain = &url[domain_start..];
+    let domain = &domain[..domain
+        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(domain.len())];
+    let parts: Vec<_> = domain.split('.').collect();
+    let valid = parts.len() >= 2
+        && parts.iter().all(|part| !part.is_empty())
+        && parts.iter().rev().take(2).all(|part| !part.contains('_'));
+    valid.then_some(url)
+}
+
+/// The key of the `[[key]]` the text starts with, on a single line without brackets.
+fn kbd(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix("[[")?;
+    let key = &rest[..rest.find("]]")?];
+    let valid = !key.trim().is_empty() && !key.contains(['[', ']', '\n']);
+    valid.then_some(key)
+}
+
+/// Parses CommonMark emphasis from delimiters matched by [`match_emphasis`]. One matched
+/// delimiter makes italic text, two bold. Delimiters without a match are text.
+fn emphasis(
+    text: &MappedText,
+    chars: &mut Cursor,
+    end: usize,
+    state: &mut ParseState,
+) -> Result<Option<(Inline, Vec<SpanTree>)>, MdParseError> {
+    let from = offset(chars, end);
+    let Some(&(used, close)) = state
+        .emphasis
+        .get(&from)
+        .filter(|&&(used, close)| close + used <= end)
+    else {
+        advance(chars, from + 1);
+        return Ok(Some((
+            Inline::Text(text.text[from..=from].to_string()),
+            Vec::new(),
+        )));
+    };
+    let (content, children) = parse_range(text, from + used..close, state)?;
+    advance(chars, close + used);
+    Ok(Some(if used == 1 {
+        (Inline::Italic(content), children)
+    } else {
+        (Inline::Bold(content), children)
+    }))
+}
+
+/// Matches the `*` and `_` delimiters of the text the way CommonMark does: every run that can
+/// close emphasis is matched with the closest run before it that can open it, taking two
+/// delimiters from both if they have them and one otherwise, until it runs out. Delimiters are
+/// taken from the inner sides of the runs, and those between a matched pair are left as text.
+/// Those in code spans and autolinks, or in bare URLs with `bare_urls`, don't count.
+///
+/// Returns, by the offset of the opening delimiters, the number of delimiters matched and the
+/// offset of the closing ones.
+pub(super) fn match_emphasis(input: &str, bare_urls: bool) -> HashMap<usize, (usize, usize)> {
+    struct Run {
+        delimiter: char,
+        start: usize,
+        len: usize,
+        original_len: usize,
+        open: bool,
+        close: bool,
+    }
+
+    let end = input.len();
+    let mut runs: Vec<Run> = Vec::new();
+    let mut i = 0;
+    while let Some(c) = input[i..].chars().next() {
+        match c {
+            '\\' => {
+                i += 1;
+                i += input[i..].chars().next().map_or(0, char::len_utf8);
+            }
+            '`' => {
+                let n = run_length(input, i, end, '`');
+                i = closing_backticks(input, i + n, end, n).unwrap_or(i) + n;
+            }
+            // delimiters in links are part of the URL
+            '<' if let Some((url, _)) = autolink(&input[i..]) => i += url.len() + 2,
+            'h' | 'w' if bare_urls && let Some(url) = bare_url(input, i) => i += url.len(),
+            '*' | '_' => {
+                let len = run_length(input, i, end, c);
+                let (open, close) = flanking(
+                    c,
+                    input[..i].chars().next_back(),
+                    input[i + len..].chars().next(),
+                );
+                runs.push(Run {
+                    delimiter: c,
+                    start: i,
+                    len,
+                    original_len: len,
+                    open,
+                    close,
+                });
+                i += len;
+            }
+            _ => i += c.len_utf8(),
+        }
+    }
+
+    let mut matches = HashMap::new();
+    let mut closer = 0;
+    while closer < runs.len() {
+        let candidate = &runs[closer];
+        let opener = (0..closer).rev().find(|&o| {
+            let opener = &runs[o];
+            // runs that can both open and close only match if their lengths don't add up to a
+            // multiple of 3, unless both are, so `*foo**bar*` stays one emphasis
+            let multiple_of_3 = (opener.original_len + candidate.original_len) % 3 == 0
+                && !(opener.original_len % 3 == 0 && candidate.original_len % 3 == 0);
+            opener.delimiter == candidate.delimiter
+                && opener.open
+                && opener.len > 0
+                && !((opener.close || candidate.open) && multiple_of_3)
+        });
+        let Some(opener) = opener.filter(|_| candidate.close && candidate.len > 0) else {
+            closer += 1;
+            continue;
+        };
+        let used = if runs[opener].len >= 2 && runs[closer].len >= 2 {
+            2
+        } else {
+            1
+        };
+        runs[opener].len -= used;
+        matches.insert(
+            runs[opener].start + runs[opener].len,
+            (used, runs[closer].start),
+        );
+        runs[closer].start += used;
+        runs[closer].len -= used;
+        for run in &mut runs[opener + 1..closer] {
+            run.len = 0;
+        }
+    }
+    matches
+}
+
+/// Whether a run of `delimiter` between `before` and `after` can open and close emphasis, by
+/// the CommonMark rules for left- and right-flanking runs. `_` does not work inside words.
+fn flanking(delimiter: char, before: Option<char>, after: Option<char>) -> (bool, bool) {
+    let space = |c: Option<char>| c.is_none_or(char::is_whitespace);
+    let punctuation = |c: Option<char>| {
+        c.is_some_and(|c| c.is_ascii_punctuation() || !(c.is_alphanumeric() || c.is_whitespace()))
+    };
+    let left = !space(after) && (!punctuation(after) || space(before) || punctuation(before));
+    let right = !space(before) && (!punctuation(before) || space(after) || punctuation(after));
+    if delimiter == '*' {
+        (left, right)
+    } else {
+        (
+            left && (!right || punctuation(before)),
+            right && (!left || punctuation(after)),
+        )
+    }
+}
+
+/// Parses a CommonMark code span: the content between runs of as many backticks, with line
+/// breaks as spaces and one space stripped from both sides. A run without a match is text.
+fn code_span(text: &MappedText, chars: &mut Cursor, end: usize) -> Inline {
+    let input = &text.text;
+    let from = offset(chars, end);
+    let n = run_length(input, from, end, '`');
+    let Some(close) = closing_backticks(input, from + n, end, n) else {
+        advance(chars, from + n);
+        return Inline::Text(input[from..from + n].to_string());
+    };
+    let mut code = input[from + n..close].replace('\n', " ");
+    if code.len() > 1 && code.starts_with(' ') && code.ends_with(' ') && code.trim() != "" {
+        code = code[1..code.len() - 1].to_string();
+    }
+    advance(chars, close + n);
+    Inline::Code(code)
+}
+
+/// Finds the run of exactly `n` backticks after `from`.
+fn closing_backticks(input: &str, from: usize, end: usize, n: usize) -> Option<usize> {
+    let mut i = from;
+    while let Some(found) = input[i..end].find('`') {
+        let start = i + found;
+        let m = run_length(input, start, end, '`');
+        if m == n {
+            return Some(start);
+        }
+        i = start + m;
+    }
+    None
+}
+
+/// Length of the run of `c` at `from`, in bytes.
+fn run_length(input: &str, from: usize, end: usize, c: char) -> usize {
+    let rest = &input[from..end];
+    rest.len() - rest.trim_start_matches(c).len()
+}
+
+/// Consumes the characters before `to`.
+fn advance(chars: &mut Cursor, to: usize) {
+    while chars.next_if(|&(i, _)| i < to).is_some() {}
+}
+
+/// Whether the next characters are `![`, the start of an image.
+fn starts_image(chars: &Cursor) -> bool {
+    let mut ahead = chars.clone();
+    ahead.next().map(|(_, c)| c) == Some('!') && ahead.next().map(|(_, c)| c) == Some('[')
+}
+
+/// Collects the target of a link or image after its text: an inline `(destination "title")`,
+/// or the definition the reference names, `[label]`, or the text itself with `[]` or nothing.
+fn link_target(
+    text: &MappedText,
+    chars: &mut Cursor,
+    end: usize,
+    label: Range<usize>,
+    expected: &str,
+    state: &ParseState,
+) -> Result<(String, Option<String>), MdParseError> {
+    let at = offset(chars, end);
+    if chars.next_if(|&(_, c)| c == '(').is_some() {
+        return collect_link_target(text, chars, end);
+    }
+
+    let mut ahead = chars.clone();
+    let mut label = label;
+    if ahead.next_if(|&(_, c)| c == '[').is_some() {
+        let reference = collect_label(&mut ahead, state).map_err(|e| e.at(text.position(at)))?;
+        if !reference.is_empty() {
+            label = reference;
+        }
+    } else {
+        ahead = chars.clone();
+    }
+    match state.links.get(&label_key(&text.text[label])) {
+        Some(definition) => {
+            *chars = ahead;
+            Ok(definition.clone())
+        }
+        None => Err(MdParseError::new(
+            format!("{expected} or a defined reference"),
+            got(chars.peek().map(|&(_, c)| c)),
+        )
+        .at(text.position(at))),
+    }
+}
+
+/// Collects the `destination "optional title")` part of a link or image after the opening `(`.
+/// The title may be quoted with `"`, `'` or wrapped in `()`, a destination with spaces in `<>`.
+fn collect_link_target(
+    text: &MappedText,
+    chars: &mut Cursor,
+    end: usize,
+) -> Result<(String, Option<String>), MdParseError> {
+    skip_whitespace(chars);
+    let mut destination = String::new();
+    if let Some((open, _)) = chars.next_if(|&(_, c)| c == '<') {
+        let range = collect_until(chars, '>').map_err(|e| e.at(text.position(open)))?;
+        destination.push_str(&text.text[range]);
+    } else {
+        // parentheses are allowed in the destination, as long as they are balanced
+        let mut depth = 0;
+        while let Some(&(_, c)) = chars.peek() {
+            match c {
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                '(' => depth += 1,
+                c if c.is_whitespace() => break,
+                _ => {}
+            }
+            destination.push(c);
+            chars.next();
+        }
+    }
+
+    skip_whitespace(chars);
+    let title = match chars.peek().copied() {
+        Some((open, quote @ ('"' | '\'' | '('))) => {
+            chars.next();
+            let close = if quote == '(' { ')' } else { quote };
+            let range = collect_until(chars, close).map_err(|e| e.at(text.position(open)))?;
+            Some(text.text[range].to_string())
+        }
+        _ => None,
+    };
+
+    skip_whitespace(chars);
+    let at = offset(chars, end);
+    match chars.next() {
+        Some((_, ')')) => Ok((destination, title)),
+        found => Err(MdParseError::new(')', got(found.map(|(_, c)| c))).at(text.position(at))),
+    }
+}
+
+/// The character found instead of the expected one, for an error message.
+fn got(c: Option<char>) -> String {
+    c.map_or_else(|| "end of text".to_string(), String::from)
+}
+
+fn skip_whitespace(chars: &mut Cursor) {
+    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+}
 
-    Ok(inlines)
+/// Consumes the text of a link or image up to and including its `]`. CommonMark allows
+/// balanced or escaped brackets inside it.
+fn collect_label(chars: &mut Cursor, state: &ParseState) -> Result<Range<usize>, MdParseError> {
+    if !state.commonmark() {
+        return collect_until(chars, ']');
+    }
+    let start = chars.peek().map(|&(i, _)| i);
+    let mut depth = 0;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => depth += 1,
+            ']' if depth == 0 => return Ok(start.unwrap_or(i)..i),
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    Err(MdParseError::new(']', ""))
 }
 
-fn collect_until<I: Iterator<Item = char>>(
-    chars: &mut std::iter::Peekable<I>,
-    end: char,
-) -> Result<String, MdParseError> {
-    let mut s = String::new();
-    for c in chars.by_ref() {
+/// Consumes characters up to and including `end`, returning the range of the ones before it.
+fn collect_until(chars: &mut Cursor, end: char) -> Result<Range<usize>, MdParseError> {
+    let start = chars.peek().map(|&(i, _)| i);
+    for (i, c) in chars.by_ref() {
         if c == end {
-            return Ok(s);
+            return Ok(start.unwrap_or(i)..i);
         }
-        s.push(c);
     }
     Err(MdParseError::new(end, ""))
 }
 
 #[cfg(test)]
 mod test {
-    use crate::ast::Inline;
+    use crate::{
+        MdParseError,
+        ast::Inline,
+        parser::{
+            Dialect, Extensions, ParseState,
+            footnote::Footnotes,
+            source::{MappedText, Source},
+        },
+    };
+
+    use super::{chars, collect_until};
+    use std::collections::HashMap;
+
+    /// Parses a single line of inline elements.
+    fn parse_inlines(md: &str) -> Result<Vec<Inline>, MdParseError> {
+        parse_with(md, true).map(|(inlines, _)| inlines)
+    }
+
+    fn parse_with(
+        md: &str,
+        strict: bool,
+    ) -> Result<(Vec<Inline>, Vec<MdParseError>), MdParseError> {
+        let source = Source::new(md);
+        let mut state = ParseState {
+            strict,
+            dialect: Dialect::Gravel,
+            extensions: Extensions::default(),
+            warnings: Vec::new(),
+            emphasis: HashMap::new(),
+            footnotes: Footnotes::default(),
+            links: HashMap::new(),
+            inlines: true,
+        };
+        let (inlines, _) =
+            super::parse_inlines(&MappedText::from_line(&source, (0, md)), &mut state)?;
+        Ok((inlines, state.warnings))
+    }
 
-    use super::{collect_until, parse_inlines};
+    /// Parses leniently, returning the inline elements and the number of warnings.
+    fn parse_lenient(md: &str) -> (Vec<Inline>, usize) {
+        let (inlines, warnings) = parse_with(md, false).unwrap();
+        (inlines, warnings.len())
+    }
 
     #[test]
     fn collect_until_without_end() {
-        let mut s = "abcdef".chars().peekable();
+        let mut s = chars("abcdef", 0..6);
         let res = collect_until(&mut s, '.');
         assert!(res.is_err());
     }
@@ -153,7 +902,8 @@ mod test {
                 Inline::Text("a link to ".to_string()),
                 Inline::Link {
                     text: vec![Inline::Text("my site".to_string())],
-                    href: "https://example.com".to_string()
+                    href: "https://example.com".to_string(),
+                    title: None
                 }
             ]
         );
@@ -180,4 +930,261 @@ mod test {
         let inl = parse_inlines(md).unwrap();
         assert_eq!(inl, vec![Inline::Text(r"backslash \".to_string())]);
     }
+
+    #[test]
+    fn hard_break_trailing_spaces() {
+        let md = "line one  \nline two";
+        let inl = parse_inlines(md).unwrap();
+        assert_eq!(
+            inl,
+            vec![
+                Inline::Text("line one".to_string()),
+                Inline::LineBreak,
+                Inline::Text("line two".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn hard_break_backslash() {
+        let md = "*bold*\\\nnext";
+        let inl = parse_inlines(md).unwrap();
+        assert_eq!(
+            inl,
+            vec![
+                Inline::Bold(vec![Inline::Text("bold".to_string())]),
+                Inline::LineBreak,
+                Inline::Text("next".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn soft_break_drops_single_trailing_space() {
+        let md = "one \ntwo";
+        let inl = parse_inlines(md).unwrap();
+        assert_eq!(inl, vec![Inline::Text("one\ntwo".to_string())]);
+    }
+
+    #[test]
+    fn image_with_title() {
+        let md = r#"see ![a *cat*](img/cat.png "My cat")"#;
+        let inl = parse_inlines(md).unwrap();
+        assert_eq!(
+            inl,
+            vec![
+                Inline::Text("see ".to_string()),
+                Inline::Image {
+                    alt: "a cat".to_string(),
+                    src: "img/cat.png".to_string(),
+                    title: Some("My cat".to_string())
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn exclamation_before_link() {
+        let md = "wow! [link](a.html)";
+        let inl = parse_inlines(md).unwrap();
+        assert_eq!(
+            inl,
+            vec![
+                Inline::Text("wow! ".to_string()),
+                Inline::Link {
+                    text: vec![Inline::Text("link".to_string())],
+                    href: "a.html".to_string(),
+                    title: None
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn hyperlink_titles_and_parentheses() {
+        let md =
+            "[a](https://en.wikipedia.org/wiki/Rust_(language) 'wiki') [b](<my page.html> (page))";
+        let inl = parse_inlines(md).unwrap();
+        assert_eq!(
+            inl,
+            vec![
+                Inline::Link {
+                    text: vec![Inline::Text("a".to_string())],
+                    href: "https://en.wikipedia.org/wiki/Rust_(language)".to_string(),
+                    title: Some("wiki".to_string())
+                },
+                Inline::Text(" ".to_string()),
+                Inline::Link {
+                    text: vec![Inline::Text("b".to_string())],
+                    href: "my page.html".to_string(),
+                    title: Some("page".to_string())
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn hyperlink_unterminated_title() {
+        let md = "[a](b.html \"title)";
+        assert!(parse_inlines(md).is_err());
+    }
+
+    #[test]
+    fn lenient_unclosed_delimiters_are_text() {
+        assert_eq!(
+            parse_lenient("5 * 3 = 15"),
+            (vec![Inline::Text("5 * 3 = 15".to_string())], 1)
+        );
+        assert_eq!(
+            parse_lenient("a `tick and *bold*"),
+            (
+                vec![
+                    Inline::Text("a `tick and ".to_string()),
+                    Inline::Bold(vec![Inline::Text("bold".to_string())])
+                ],
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn lenient_brackets_without_target_are_text() {
+        assert_eq!(
+            parse_lenient("[a *b*] and [c](d.html"),
+            (
+                vec![
+                    Inline::Text("[a ".to_string()),
+                    Inline::Bold(vec![Inline::Text("b".to_string())]),
+                    Inline::Text("] and [c](d.html".to_string())
+                ],
+                2
+            )
+        );
+        // the `[` of the image is not reported again as a link
+        assert_eq!(
+            parse_lenient("![alt] [link](x.html)"),
+            (
+                vec![
+                    Inline::Text("![alt] ".to_string()),
+                    Inline::Link {
+                        text: vec![Inline::Text("link".to_string())],
+                        href: "x.html".to_string(),
+                        title: None
+                    }
+                ],
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn strict_unclosed_delimiter_fails() {
+        assert!(parse_inlines("5 * 3").is_err());
+    }
+
+    #[test]
+    fn syntax_extensions() {
+        let text = |s: &str| Inline::Text(s.to_string());
+        assert_eq!(
+            parse_inlines("~~old *news*~~ ==new== H~2~O x^2^ [[Ctrl]]+[[C]]").unwrap(),
+            vec![
+                Inline::Strikethrough(vec![text("old "), Inline::Bold(vec![text("news")])]),
+                text(" "),
+                Inline::Highlight(vec![text("new")]),
+                text(" H"),
+                Inline::Subscript(vec![text("2")]),
+                text("O x"),
+                Inline::Superscript(vec![text("2")]),
+                text(" "),
+                Inline::Kbd("Ctrl".to_string()),
+                text("+"),
+                Inline::Kbd("C".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_extensions_are_text() {
+        let md = "a == b, ~5 min, 2^10 ~ 1e3, ~~ x ~~ and \\~~no~~";
+        assert_eq!(
+            parse_inlines(md).unwrap(),
+            vec![Inline::Text(
+                "a == b, ~5 min, 2^10 ~ 1e3, ~~ x ~~ and ~~no~~".to_string()
+            )]
+        );
+        assert_eq!(
+            parse_inlines("==a \\== b== ~~~x~~~").unwrap(),
+            vec![
+                Inline::Highlight(vec![Inline::Text("a == b".to_string())]),
+                Inline::Text(" ~".to_string()),
+                Inline::Strikethrough(vec![Inline::Text("x".to_string())]),
+                Inline::Text("~".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_inlines("~a b~ ^a b^ ~~~x~~~").unwrap(),
+            vec![
+                Inline::Text("~a b~ ^a b^ ~".to_string()),
+                Inline::Strikethrough(vec![Inline::Text("x".to_string())]),
+                Inline::Text("~".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn autolinks() {
+        let link = |url: &str, href: &str| Inline::Link {
+            text: vec![Inline::Text(url.to_string())],
+            href: href.to_string(),
+            title: None,
+        };
+        assert_eq!(
+            parse_inlines(
+                "see <https://example.com/a_b*c*>, <mailto:me@example.com> or <me@example.com>"
+            )
+            .unwrap(),
+            vec![
+                Inline::Text("see ".to_string()),
+                link("https://example.com/a_b*c*", "https://example.com/a_b*c*"),
+                Inline::Text(", ".to_string()),
+                link("mailto:me@example.com", "mailto:me@example.com"),
+                Inline::Text(" or ".to_string()),
+                link("me@example.com", "mailto:me@example.com"),
+            ]
+        );
+        // bare URLs are an extension that is off by default
+        assert_eq!(
+            parse_inlines("a < b, <not a link>, <x:>, <a@b@c> and https://example.com").unwrap(),
+            vec![Inline::Text(
+                "a < b, <not a link>, <x:>, <a@b@c> and https://example.com".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn bare_url_punctuation() {
+        fn url(text: &str) -> Option<&str> {
+            super::bare_url(text, 0)
+        }
+        assert_eq!(url("https://example.com."), Some("https://example.com"));
+        assert_eq!(
+            url("www.example.com/a?b=c!?"),
+            Some("www.example.com/a?b=c")
+        );
+        assert_eq!(
+            url("https://en.wikipedia.org/wiki/Rust_(language))."),
+            Some("https://en.wikipedia.org/wiki/Rust_(language)")
+        );
+        assert_eq!(url("www.example.com/a)b)"), Some("www.example.com/a)b"));
+        assert_eq!(
+            url("www.example.com/?q=a&hl;"),
+            Some("www.example.com/?q=a")
+        );
+        assert_eq!(url("http://a.b/c<d"), Some("http://a.b/c"));
+        assert_eq!(url("www."), None);
+        assert_eq!(url("https://localhost/a"), None);
+        assert_eq!(url("http://a_b.c_d"), None);
+        assert_eq!(url("https://"), None);
+        assert_eq!(super::bare_url("xhttps://example.com", 1), None);
+    }
 }
@@ -0,0 +1,104 @@
+//! Positions in the source text, for the spans of nodes and the locations of errors.
+
+use crate::ast::{Position, Span, SpanTree};
+
+/// A line of the source, or the part of it left inside a container block, with the byte offset
+/// where it starts in the source.
+pub type Line<'a> = (usize, &'a str);
+
+/// The source text with the offsets where its lines start.
+pub struct Source<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { text, line_starts }
+    }
+
+    /// The lines of the source as `str::lines` splits them, with their offsets.
+    pub fn lines(&self) -> Vec<Line<'a>> {
+        self.text
+            .lines()
+            .zip(&self.line_starts)
+            .map(|(line, &start)| (start, line))
+            .collect()
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.line_starts[line];
+        Position {
+            offset,
+            line: line + 1,
+            col: self.text[start..offset].chars().count() + 1,
+        }
+    }
+
+    pub fn span(&self, start: usize, end: usize, children: Vec<SpanTree>) -> SpanTree {
+        SpanTree {
+            span: Span {
+                start: self.position(start),
+                end: self.position(end),
+            },
+            children,
+        }
+    }
+}
+
+/// Text put together from parts of source lines, like the lines of a paragraph joined with `\n`,
+/// which keeps where each part came from so offsets in the text can be traced back.
+pub struct MappedText<'s, 'a> {
+    pub text: String,
+    source: &'s Source<'a>,
+    /// Offsets of the parts in the text and in the source.
+    parts: Vec<(usize, usize)>,
+}
+
+impl<'s, 'a> MappedText<'s, 'a> {
+    pub fn new(source: &'s Source<'a>) -> Self {
+        Self {
+            text: String::new(),
+            source,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Text of a single part of a line.
+    pub fn from_line(source: &'s Source<'a>, line: Line) -> Self {
+        let mut text = Self::new(source);
+        text.push_line(line);
+        text
+    }
+
+    /// Appends a line, separated from the previous one by `\n`.
+    pub fn push_line(&mut self, (offset, line): Line) {
+        if !self.parts.is_empty() {
+            self.text.push('\n');
+        }
+        self.parts.push((self.text.len(), offset));
+        self.text.push_str(line);
+    }
+
+    /// Maps an offset in the text to the source. The `\n` between two lines maps to the end of
+    /// the first one.
+    pub fn source_offset(&self, offset: usize) -> usize {
+        let part = self.parts.partition_point(|&(start, _)| start <= offset);
+        self.parts
+            .get(part.wrapping_sub(1))
+            .map_or(offset, |&(start, source)| source + offset - start)
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        self.source.position(self.source_offset(offset))
+    }
+
+    pub fn span(&self, start: usize, end: usize, children: Vec<SpanTree>) -> SpanTree {
+        self.source
+            .span(self.source_offset(start), self.source_offset(end), children)
+    }
+}
@@ -0,0 +1,643 @@
+//! A small template engine for page layouts.
+//!
+//! - `{{ page.title }}` inserts a variable, HTML-escaped unless it already is HTML (like `content`).
+//!   Missing fields like `page.subtitle` insert nothing, unknown variables are errors.
+//! - `{% if page.draft %}…{% else %}…{% endif %}`, also `{% if not page.draft %}`
+//! - `{% for tag in page.tags %}…{% endfor %}`, with `loop.index`, `loop.first` and `loop.last`
+//! - `{% include "header.html" %}` renders another template from the same set
+//! - `{# a comment #}`
+
+use std::{collections::BTreeMap, collections::HashMap, fmt::Display, fs, path::PathBuf};
+
+use fstools::crawl_fs;
+
+use crate::{
+    ast::{Metadata, Value},
+    to_html::escape_attr,
+};
+
+/// Nested includes deeper than this are assumed to be recursive.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub struct TemplateError {
+    file: PathBuf,
+    line: usize,
+    msg: String,
+}
+
+impl TemplateError {
+    pub fn new(file: impl Into<PathBuf>, line: usize, msg: impl ToString) -> Self {
+        Self {
+            file: file.into(),
+            line,
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Template error in '{}' on line {}: {}",
+            self.file.display(),
+            self.line,
+            self.msg
+        )
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// A value available to templates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateValue {
+    /// Text, escaped when inserted.
+    Text(String),
+    /// Trusted HTML, inserted as is.
+    Html(String),
+    Bool(bool),
+    Integer(i64),
+    List(Vec<TemplateValue>),
+    Map(BTreeMap<String, TemplateValue>),
+}
+
+impl TemplateValue {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Self::Text(s) | Self::Html(s) => !s.is_empty(),
+            Self::Bool(b) => *b,
+            Self::Integer(i) => *i != 0,
+            Self::List(l) => !l.is_empty(),
+            Self::Map(m) => !m.is_empty(),
+        }
+    }
+
+    fn render(&self) -> Result<String, String> {
+        match self {
+            Self::Text(s) => Ok(escape_attr(s)),
+            Self::Html(s) => Ok(s.clone()),
+            Self::Bool(b) => Ok(b.to_string()),
+            Self::Integer(i) => Ok(i.to_string()),
+            Self::List(l) => Ok(l
+                .iter()
+                .map(Self::render)
+                .collect::<Result<Vec<_>, _>>()?
+                .join(", ")),
+            Self::Map(_) => Err("a map can't be inserted, pick one of its fields".to_string()),
+        }
+    }
+}
+
+impl From<&Value> for TemplateValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::String(s) => Self::Text(s.clone()),
+            Value::Integer(i) => Self::Integer(*i),
+            Value::Boolean(b) => Self::Bool(*b),
+            Value::Date(d) => Self::Text(d.to_string()),
+            Value::Array(a) => Self::List(a.iter().map(Self::from).collect()),
+        }
+    }
+}
+
+impl From<&Metadata> for TemplateValue {
+    fn from(metadata: &Metadata) -> Self {
+        Self::Map(
+            metadata
+                .iter()
+                .map(|(k, v)| (k.clone(), v.into()))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Var {
+        line: usize,
+        path: Vec<String>,
+    },
+    If {
+        negate: bool,
+        path: Vec<String>,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        line: usize,
+        var: String,
+        path: Vec<String>,
+        body: Vec<Node>,
+    },
+    Include {
+        line: usize,
+        name: String,
+    },
+}
+
+#[derive(Debug)]
+struct Template {
+    file: PathBuf,
+    nodes: Vec<Node>,
+}
+
+/// A set of named templates that can include each other.
+#[derive(Debug, Default)]
+pub struct Templates {
+    templates: HashMap<String, Template>,
+}
+
+impl Templates {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads every `.html` file in the directory as a template, named by its path relative to it.
+    /// Other files, like images next to the templates, are skipped.
+    ///
+    /// # Errors
+    /// A file can't be read or has a syntax error.
+    pub fn load(dir: &PathBuf) -> Result<Self, TemplateError> {
+        let mut templates = Self::new();
+        let html = crawl_fs(dir)
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "html"));
+        for path in html {
+            let file = dir.join(&path);
+            let source = fs::read_to_string(&file)
+                .map_err(|e| TemplateError::new(&file, 0, format!("reading failed: {e}")))?;
+            templates.add(path.to_string_lossy(), file, &source)?;
+        }
+        Ok(templates)
+    }
+
+    /// Parses and adds a template. `file` is only used in error messages.
+    ///
+    /// # Errors
+    /// The template has a syntax error.
+    pub fn add(
+        &mut self,
+        name: impl ToString,
+        file: impl Into<PathBuf>,
+        source: &str,
+    ) -> Result<(), TemplateError> {
+        let file = file.into();
+        let tokens =
+            tokenize(source).map_err(|(line, msg)| TemplateError::new(&file, line, msg))?;
+        let mut tokens = tokens.into_iter();
+        let nodes = match parse_nodes(&mut tokens, &[]) {
+            Ok((nodes, _end)) => nodes,
+            Err((line, msg)) => Err(TemplateError::new(&file, line, msg))?,
+        };
+        self.templates
+            .insert(name.to_string(), Template { file, nodes });
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.templates.contains_key(name)
+    }
+
+    /// Renders the named template with the given variables.
+    ///
+    /// # Errors
+    /// The template doesn't exist, uses an undefined variable, or includes too deep.
+    pub fn render(
+        &self,
+        name: &str,
+        variables: &BTreeMap<String, TemplateValue>,
+    ) -> Result<String, TemplateError> {
+        let template =
+            self.templates
+                .get(name)
+                .ok_or(TemplateError::new(name, 0, "template not found"))?;
+        let mut scope = Scope {
+            root: variables,
+            locals: Vec::new(),
+        };
+        let mut out = String::new();
+        self.render_nodes(template, &template.nodes, &mut scope, &mut out, 0)?;
+        Ok(out)
+    }
+
+    fn render_nodes(
+        &self,
+        template: &Template,
+        nodes: &[Node],
+        scope: &mut Scope,
+        out: &mut String,
+        depth: usize,
+    ) -> Result<(), TemplateError> {
+        let error = |line, msg| TemplateError::new(&template.file, line, msg);
+        for node in nodes {
+            match node {
+                Node::Text(s) => out.push_str(s),
+                Node::Var { line, path } => {
+                    // a missing field is empty, e.g. optional metadata, but an unknown variable
+                    // is likely a typo
+                    if !scope.defines(&path[0]) {
+                        Err(error(*line, format!("undefined variable `{}`", path[0])))?;
+                    }
+                    if let Some(value) = scope.lookup(path) {
+                        out.push_str(&value.render().map_err(|msg| error(*line, msg))?);
+                    }
+                }
+                Node::If {
+                    negate,
+                    path,
+                    then,
+                    otherwise,
+                } => {
+                    let truthy = scope.lookup(path).is_some_and(TemplateValue::is_truthy);
+                    let branch = if truthy != *negate { then } else { otherwise };
+                    self.render_nodes(template, branch, scope, out, depth)?;
+                }
+                Node::For {
+                    line,
+                    var,
+                    path,
+                    body,
+                } => {
+                    let items = match scope.lookup(path) {
+                        Some(TemplateValue::List(items)) => items.clone(),
+                        Some(_) => {
+                            Err(error(*line, format!("`{}` is not a list", path.join("."))))?
+                        }
+                        None => Vec::new(),
+                    };
+                    let len = items.len();
+                    for (i, item) in items.into_iter().enumerate() {
+                        let info = BTreeMap::from([
+                            ("index".to_string(), TemplateValue::Integer(i as i64 + 1)),
+                            ("first".to_string(), TemplateValue::Bool(i == 0)),
+                            ("last".to_string(), TemplateValue::Bool(i + 1 == len)),
+                        ]);
+                        scope.locals.push((var.clone(), item));
+                        scope
+                            .locals
+                            .push(("loop".to_string(), TemplateValue::Map(info)));
+                        let rendered = self.render_nodes(template, body, scope, out, depth);
+                        scope.locals.truncate(scope.locals.len() - 2);
+                        rendered?;
+                    }
+                }
+                Node::Include { line, name } => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        Err(error(
+                            *line,
+                            format!(
+                                "includes nested over {MAX_INCLUDE_DEPTH} deep, is `{name}` including itself?"
+                            ),
+                        ))?;
+                    }
+                    let included = self.templates.get(name).ok_or_else(|| {
+                        error(*line, format!("included template `{name}` not found"))
+                    })?;
+                    self.render_nodes(included, &included.nodes, scope, out, depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Variables of the render, with loop variables shadowing the global ones.
+struct Scope<'a> {
+    root: &'a BTreeMap<String, TemplateValue>,
+    locals: Vec<(String, TemplateValue)>,
+}
+
+impl Scope<'_> {
+    fn defines(&self, name: &str) -> bool {
+        self.locals.iter().any(|(n, _v)| n == name) || self.root.contains_key(name)
+    }
+
+    fn lookup(&self, path: &[String]) -> Option<&TemplateValue> {
+        let (first, rest) = path.split_first()?;
+        let mut value = self
+            .locals
+            .iter()
+            .rev()
+            .find(|(name, _v)| name == first)
+            .map(|(_name, v)| v)
+            .or_else(|| self.root.get(first))?;
+        for key in rest {
+            match value {
+                TemplateValue::Map(map) => value = map.get(key)?,
+                _ => return None,
+            }
+        }
+        Some(value)
+    }
+}
+
+/// A syntax error message with its line number.
+type SyntaxError = (usize, String);
+
+enum Token<'a> {
+    Text(&'a str),
+    Var(&'a str),
+    Tag(&'a str),
+}
+
+/// Splits the source into text, `{{ … }}` and `{% … %}`, paired with their line numbers.
+/// Comments are dropped.
+fn tokenize(source: &str) -> Result<Vec<(usize, Token<'_>)>, SyntaxError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut rest = source;
+
+    while let Some(start) = ["{{", "{%", "{#"]
+        .iter()
+        .filter_map(|open| rest.find(open))
+        .min()
+    {
+        if start > 0 {
+            tokens.push((line, Token::Text(&rest[..start])));
+            line += rest[..start].matches('\n').count();
+        }
+        let close = match &rest[start..start + 2] {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let inner_start = start + 2;
+        let len = rest[inner_start..].find(close).ok_or((
+            line,
+            format!(
+                "`{}` is never closed with `{close}`",
+                &rest[start..inner_start]
+            ),
+        ))?;
+        let inner = &rest[inner_start..inner_start + len];
+        match close {
+            "}}" => tokens.push((line, Token::Var(inner.trim()))),
+            "%}" => tokens.push((line, Token::Tag(inner.trim()))),
+            _ => {}
+        }
+        line += inner.matches('\n').count();
+        rest = &rest[inner_start + len + 2..];
+    }
+    if !rest.is_empty() {
+        tokens.push((line, Token::Text(rest)));
+    }
+    Ok(tokens)
+}
+
+/// Parses nodes until one of the `end` tags, returning the nodes and the tag that ended them, or
+/// `None` at the end of the template.
+fn parse_nodes<'a, I: Iterator<Item = (usize, Token<'a>)>>(
+    tokens: &mut I,
+    end: &[&str],
+) -> Result<(Vec<Node>, Option<&'a str>), SyntaxError> {
+    let mut nodes = Vec::new();
+    while let Some((line, token)) = tokens.next() {
+        match token {
+            Token::Text(s) => nodes.push(Node::Text(s.to_string())),
+            Token::Var(var) => nodes.push(Node::Var {
+                line,
+                path: parse_path(var).map_err(|msg| (line, msg))?,
+            }),
+            Token::Tag(tag) => {
+                let words: Vec<&str> = tag.split_whitespace().collect();
+                match words.as_slice() {
+                    [word] if end.contains(word) => return Ok((nodes, Some(word))),
+                    ["if", condition @ ..] => {
+                        let (negate, path) = match condition {
+                            ["not", path] => (true, path),
+                            [path] => (false, path),
+                            _ => Err((
+                                line,
+                                format!("expected `if <var>` or `if not <var>`, got `{tag}`"),
+                            ))?,
+                        };
+                        let path = parse_path(path).map_err(|msg| (line, msg))?;
+                        let (then, mut ended) = parse_nodes(tokens, &["else", "endif"])?;
+                        let mut otherwise = Vec::new();
+                        if ended == Some("else") {
+                            (otherwise, ended) = parse_nodes(tokens, &["endif"])?;
+                        }
+                        if ended.is_none() {
+                            Err((line, "`if` is never closed with `endif`".to_string()))?;
+                        }
+                        nodes.push(Node::If {
+                            negate,
+                            path,
+                            then,
+                            otherwise,
+                        });
+                    }
+                    ["for", var, "in", path] => {
+                        let var = (*var).to_string();
+                        let path = parse_path(path).map_err(|msg| (line, msg))?;
+                        let (body, ended) = parse_nodes(tokens, &["endfor"])?;
+                        if ended.is_none() {
+                            Err((line, "`for` is never closed with `endfor`".to_string()))?;
+                        }
+                        nodes.push(Node::For {
+                            line,
+                            var,
+                            path,
+                            body,
+                        });
+                    }
+                    ["include", name] => {
+                        let name = name
+                            .strip_prefix('"')
+                            .and_then(|n| n.strip_suffix('"'))
+                            .ok_or((
+                                line,
+                                format!("expected a quoted template name, got `{name}`"),
+                            ))?;
+                        nodes.push(Node::Include {
+                            line,
+                            name: name.to_string(),
+                        });
+                    }
+                    _ => Err((line, format!("unexpected tag `{tag}`")))?,
+                }
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+/// Parses a dotted variable path like `page.title`.
+fn parse_path(s: &str) -> Result<Vec<String>, String> {
+    let path: Vec<String> = s.split('.').map(str::to_string).collect();
+    let valid = path.iter().all(|segment| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    });
+    if valid {
+        Ok(path)
+    } else {
+        Err(format!("invalid variable name `{s}`"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, fs};
+
+    use super::{TemplateValue, Templates};
+
+    fn variables() -> BTreeMap<String, TemplateValue> {
+        let page = BTreeMap::from([
+            (
+                "title".to_string(),
+                TemplateValue::Text("Tips & \"tricks\"".to_string()),
+            ),
+            (
+                "tags".to_string(),
+                TemplateValue::List(vec![
+                    TemplateValue::Text("a".to_string()),
+                    TemplateValue::Text("b".to_string()),
+                ]),
+            ),
+            ("draft".to_string(), TemplateValue::Bool(false)),
+        ]);
+        BTreeMap::from([
+            ("page".to_string(), TemplateValue::Map(page)),
+            (
+                "content".to_string(),
+                TemplateValue::Html("<p>hi</p>".to_string()),
+            ),
+        ])
+    }
+
+    fn render(source: &str) -> Result<String, String> {
+        let mut templates = Templates::new();
+        templates
+            .add("test.html", "test.html", source)
+            .map_err(|e| e.to_string())?;
+        templates
+            .render("test.html", &variables())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn variables_are_escaped_but_html_is_not() {
+        let html = render("<title>{{ page.title }}</title>{{content}}").unwrap();
+        assert_eq!(
+            html,
+            "<title>Tips &amp; &quot;tricks&quot;</title><p>hi</p>"
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        let html = render(
+            "{% if page.draft %}draft{% else %}final{% endif %}{% if not page.missing %}!{% endif %}",
+        )
+        .unwrap();
+        assert_eq!(html, "final!");
+    }
+
+    #[test]
+    fn loops() {
+        let html =
+            render("{% for tag in page.tags %}{{ loop.index }}:{{ tag }}{% if not loop.last %}, {% endif %}{% endfor %}")
+                .unwrap();
+        assert_eq!(html, "1:a, 2:b");
+    }
+
+    #[test]
+    fn includes_and_comments() {
+        let mut templates = Templates::new();
+        templates
+            .add("header.html", "header.html", "<h1>{{ page.title }}</h1>")
+            .unwrap();
+        templates
+            .add(
+                "default.html",
+                "default.html",
+                "{# page header #}{% include \"header.html\" %}{{ content }}",
+            )
+            .unwrap();
+        let html = templates.render("default.html", &variables()).unwrap();
+        assert_eq!(html, "<h1>Tips &amp; &quot;tricks&quot;</h1><p>hi</p>");
+    }
+
+    #[test]
+    fn recursive_include() {
+        let mut templates = Templates::new();
+        templates
+            .add("loop.html", "loop.html", "{% include \"loop.html\" %}")
+            .unwrap();
+        assert!(templates.render("loop.html", &variables()).is_err());
+    }
+
+    #[test]
+    fn undefined_variable_line() {
+        let err = render("<html>\n<body>\n{{ pgae.title }}").unwrap_err();
+        assert_eq!(
+            err,
+            "Template error in 'test.html' on line 3: undefined variable `pgae`"
+        );
+    }
+
+    #[test]
+    fn missing_field_is_empty() {
+        let html = render("[{{ page.subtitle }}]").unwrap();
+        assert_eq!(html, "[]");
+    }
+
+    #[test]
+    fn unclosed_block_line() {
+        let err = render("a\n{% for tag in page.tags %}\n{{ tag }}").unwrap_err();
+        assert_eq!(
+            err,
+            "Template error in 'test.html' on line 2: `for` is never closed with `endfor`"
+        );
+    }
+
+    #[test]
+    fn unclosed_tag_line() {
+        let err = render("a\n\nb {{ page.title").unwrap_err();
+        assert_eq!(
+            err,
+            "Template error in 'test.html' on line 3: `{{` is never closed with `}}`"
+        );
+    }
+
+    #[test]
+    fn stray_end_tag() {
+        let err = render("{% endif %}").unwrap_err();
+        assert!(err.contains("unexpected tag `endif`"));
+    }
+
+    #[test]
+    fn load_skips_other_files() {
+        let dir = std::env::temp_dir().join(format!("cracked_md-{}-templates", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        fs::write(
+            dir.join("default.html"),
+            "{% include \"partials/header.html\" %}",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("partials/header.html"),
+            "<h1>{{ page.title }}</h1>",
+        )
+        .unwrap();
+        fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G', 0xff, 0xfe]).unwrap();
+
+        let templates = Templates::load(&dir).unwrap();
+
+        assert!(!templates.contains("logo.png"));
+        assert_eq!(
+            templates.render("default.html", &variables()).unwrap(),
+            "<h1>Tips &amp; &quot;tricks&quot;</h1>"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
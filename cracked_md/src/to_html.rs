@@ -1,46 +1,313 @@
 //! A trait + implementations for generating HTML.
 
-use crate::ast::{Block, Document, Inline};
+use crate::{
+    ast::{Alignment, Block, Document, Footnote, Inline, ListItem, TocEntry, parse_line_ranges},
+    highlight::{Grammars, token_html},
+};
+use std::collections::BTreeMap;
 
-pub trait ToHtml {
-    fn to_html(self) -> String;
+pub trait ToHtml: Sized {
+    /// Renders with code blocks highlighted by the built-in grammars.
+    fn to_html(self) -> String {
+        self.to_html_with(Grammars::builtin())
+    }
+
+    /// Renders with code blocks highlighted by the given grammars.
+    fn to_html_with(self, grammars: &Grammars) -> String;
 }
 
 impl ToHtml for Document {
-    fn to_html(self) -> String {
+    fn to_html_with(self, grammars: &Grammars) -> String {
+        let head = self
+            .metadata
+            .title()
+            .map(|t| format!("<title>{}</title>", escape_text(t)))
+            .unwrap_or_default();
         format!(
-            "<!doctype html><html lang=en><head></head><body>{}</body></html>",
-            self.blocks.to_html()
+            "<!doctype html><html lang=en><head>{head}</head><body>{}</body></html>",
+            self.blocks.to_html_with(grammars)
         )
     }
 }
 
 impl ToHtml for Block {
-    fn to_html(self) -> String {
+    fn to_html_with(self, grammars: &Grammars) -> String {
         match self {
-            Self::Paragraph(content) => format!("<p>{}</p>", content.to_html()),
-            Self::Heading { level, content } => {
-                format!("<h{}>{}</h{}>", level, content.to_html(), level)
+            Self::Paragraph(content) => format!("<p>{}</p>", content.to_html_with(grammars)),
+            Self::Heading { level, content, id } if id.is_empty() => {
+                format!(
+                    "<h{}>{}</h{}>",
+                    level,
+                    content.to_html_with(grammars),
+                    level
+                )
             }
+            Self::Heading { level, content, id } => format!(
+                "<h{} id=\"{}\">{}</h{}>",
+                level,
+                escape_attr(&id),
+                content.to_html_with(grammars),
+                level
+            ),
             Self::Code {
-                language: _,
+                language,
                 content,
+                attributes,
+            } => code_block(language, &content, &attributes, grammars),
+            Self::Footnotes(footnotes) => format!(
+                "<section class=\"footnotes\"><ol>{}</ol></section>",
+                footnotes.to_html_with(grammars)
+            ),
+            Self::List(items) => format!("<ul>{}</ul>", items.to_html_with(grammars)),
+            Self::OrderedList { start: 1, items } => {
+                format!("<ol>{}</ol>", items.to_html_with(grammars))
+            }
+            Self::OrderedList { start, items } => {
+                format!(
+                    "<ol start=\"{start}\">{}</ol>",
+                    items.to_html_with(grammars)
+                )
+            }
+            Self::Quote(blocks) => {
+                format!("<blockquote>{}</blockquote>", blocks.to_html_with(grammars))
+            }
+            Self::Table {
+                header,
+                alignments,
+                rows,
             } => {
-                format!("<pre><code>{content}</code></pre>")
+                let mut rendered = format!(
+                    "<table><thead>{}</thead>",
+                    table_row(header, &alignments, "th", grammars)
+                );
+                if !rows.is_empty() {
+                    rendered.push_str("<tbody>");
+                    for row in rows {
+                        rendered.push_str(&table_row(row, &alignments, "td", grammars));
+                    }
+                    rendered.push_str("</tbody>");
+                }
+                rendered.push_str("</table>");
+                rendered
+            }
+            Self::Toc(entries) if entries.is_empty() => String::new(),
+            Self::Toc(entries) => {
+                format!(
+                    "<nav class=\"toc\"><ul>{}</ul></nav>",
+                    entries.to_html_with(grammars)
+                )
+            }
+            Self::ThematicBreak => "<hr>".to_string(),
+        }
+    }
+}
+
+impl ToHtml for TocEntry {
+    fn to_html_with(self, grammars: &Grammars) -> String {
+        let children = if self.children.is_empty() {
+            String::new()
+        } else {
+            format!("<ul>{}</ul>", self.children.to_html_with(grammars))
+        };
+        format!(
+            "<li><a href=\"#{}\">{}</a>{}</li>",
+            escape_attr(&self.id),
+            escape_text(&self.text),
+            children
+        )
+    }
+}
+
+/// Renders a code block. With `linenos` or `hl_lines` every line is wrapped in a
+/// `<span class="line">` to number or emphasize it, and a `title` puts the block in a captioned
+/// `<figure>`.
+fn code_block(
+    language: Option<String>,
+    content: &str,
+    attributes: &BTreeMap<String, String>,
+    grammars: &Grammars,
+) -> String {
+    // unknown languages are plain text, still marked for highlighters in the browser
+    let tokens = language
+        .as_deref()
+        .and_then(|l| grammars.get(l))
+        .map_or_else(|| vec![(None, content)], |g| g.tokens(content));
+    let linenos = attributes.get("linenos").is_some_and(|v| v != "false");
+    let highlighted = attributes
+        .get("hl_lines")
+        .and_then(|l| parse_line_ranges(l))
+        .unwrap_or_default();
+
+    let mut code = String::new();
+    if linenos || !highlighted.is_empty() {
+        // split the tokens into lines, so that spans don't cross line ends
+        let mut lines = vec![String::new()];
+        for (class, text) in tokens {
+            for (i, part) in text.split('\n').enumerate() {
+                if i > 0 {
+                    lines.push(String::new());
+                }
+                if !part.is_empty() {
+                    lines.last_mut().unwrap().push_str(&token_html(class, part));
+                }
+            }
+        }
+        if lines.len() > 1 && lines.last().is_some_and(String::is_empty) {
+            lines.pop();
+        }
+        for (i, line) in lines.iter().enumerate() {
+            let number = i + 1;
+            let class = if highlighted.iter().any(|r| r.contains(&number)) {
+                "line hl"
+            } else {
+                "line"
+            };
+            code.push_str(&format!("<span class=\"{class}\">"));
+            if linenos {
+                code.push_str(&format!("<span class=\"lineno\">{number}</span>"));
+            }
+            code.push_str(line);
+            code.push_str("</span>\n");
+        }
+    } else {
+        for (class, text) in tokens {
+            code.push_str(&token_html(class, text));
+        }
+    }
+
+    let pre = match language {
+        Some(language) => format!(
+            "<pre><code class=\"language-{}\">{code}</code></pre>",
+            escape_attr(&language)
+        ),
+        None => format!("<pre><code>{code}</code></pre>"),
+    };
+    match attributes.get("title") {
+        Some(title) => format!(
+            "<figure class=\"code\"><figcaption>{}</figcaption>{pre}</figure>",
+            escape_text(title)
+        ),
+        None => pre,
+    }
+}
+
+/// Renders a table row with cells in the given tag, aligned per column.
+fn table_row(
+    cells: Vec<Vec<Inline>>,
+    alignments: &[Alignment],
+    tag: &str,
+    grammars: &Grammars,
+) -> String {
+    let mut rendered = String::from("<tr>");
+    for (cell, alignment) in cells.into_iter().zip(alignments) {
+        let style = match alignment {
+            Alignment::None => "",
+            Alignment::Left => " style=\"text-align: left\"",
+            Alignment::Center => " style=\"text-align: center\"",
+            Alignment::Right => " style=\"text-align: right\"",
+        };
+        rendered.push_str(&format!(
+            "<{tag}{style}>{}</{tag}>",
+            cell.to_html_with(grammars)
+        ));
+    }
+    rendered.push_str("</tr>");
+    rendered
+}
+
+impl ToHtml for ListItem {
+    fn to_html_with(self, grammars: &Grammars) -> String {
+        // a lone paragraph is rendered without `<p>`, so simple lists stay compact
+        let paragraphs = self
+            .blocks
+            .iter()
+            .filter(|b| matches!(b, Block::Paragraph(_)))
+            .count();
+        let mut rendered = String::new();
+        for block in self.blocks {
+            match block {
+                Block::Paragraph(content) if paragraphs == 1 => {
+                    rendered.push_str(&content.to_html_with(grammars));
+                }
+                _ => rendered.push_str(&block.to_html_with(grammars)),
             }
-            _ => todo!(),
         }
+        match self.checked {
+            Some(checked) => format!(
+                "<li class=\"task\"><input type=\"checkbox\" disabled{}> {rendered}</li>",
+                if checked { " checked" } else { "" }
+            ),
+            None => format!("<li>{rendered}</li>"),
+        }
+    }
+}
+
+impl ToHtml for Footnote {
+    fn to_html_with(self, grammars: &Grammars) -> String {
+        let mut back_links = String::new();
+        for occurrence in 1..=self.references {
+            let mark = if occurrence == 1 {
+                "↩".to_string()
+            } else {
+                format!("↩<sup>{occurrence}</sup>")
+            };
+            back_links.push_str(&format!(
+                " <a href=\"#{}\" class=\"footnote-backref\">{mark}</a>",
+                footnote_ref_id(self.number, occurrence)
+            ));
+        }
+        // the links back go at the end of the last paragraph, if the footnote ends with one
+        let mut rendered = self.blocks.to_html_with(grammars);
+        match rendered.strip_suffix("</p>") {
+            Some(paragraph) => rendered = format!("{paragraph}{back_links}</p>"),
+            None => rendered.push_str(&back_links),
+        }
+        format!("<li id=\"fn-{}\">{rendered}</li>", self.number)
+    }
+}
+
+/// Id of a reference to a footnote, which its link back points to.
+fn footnote_ref_id(number: usize, occurrence: usize) -> String {
+    if occurrence > 1 {
+        format!("fnref-{number}-{occurrence}")
+    } else {
+        format!("fnref-{number}")
     }
 }
 
 impl ToHtml for Inline {
-    fn to_html(self) -> String {
+    fn to_html_with(self, grammars: &Grammars) -> String {
         match self {
-            Self::Text(s) => s,
-            Self::Bold(content) => format!("<b>{}</b>", content.to_html()),
-            Self::Italic(content) => format!("<i>{}</i>", content.to_html()),
-            Self::Code(s) => format!("<code>{s}</code>"),
-            Self::Link { text, href } => format!("<a href=\"{}\">{}</a>", href, text.to_html()),
+            Self::Text(s) => escape_text(&s),
+            Self::Bold(content) => format!("<b>{}</b>", content.to_html_with(grammars)),
+            Self::Italic(content) => format!("<i>{}</i>", content.to_html_with(grammars)),
+            Self::Strikethrough(content) => {
+                format!("<del>{}</del>", content.to_html_with(grammars))
+            }
+            Self::Highlight(content) => format!("<mark>{}</mark>", content.to_html_with(grammars)),
+            Self::Subscript(content) => format!("<sub>{}</sub>", content.to_html_with(grammars)),
+            Self::Superscript(content) => format!("<sup>{}</sup>", content.to_html_with(grammars)),
+            Self::Kbd(key) => format!("<kbd>{}</kbd>", escape_text(&key)),
+            Self::Code(s) => format!("<code>{}</code>", escape_text(&s)),
+            Self::Link { text, href, title } => format!(
+                "<a href=\"{}\"{}>{}</a>",
+                escape_attr(sanitize_url(&href)),
+                title_attr(title),
+                text.to_html_with(grammars)
+            ),
+            Self::Image { alt, src, title } => format!(
+                "<img src=\"{}\" alt=\"{}\"{}>",
+                escape_attr(sanitize_url(&src)),
+                escape_attr(&alt),
+                title_attr(title)
+            ),
+            Self::LineBreak => "<br>".to_string(),
+            Self::FootnoteRef {
+                number, occurrence, ..
+            } => format!(
+                "<sup class=\"footnote-ref\"><a href=\"#fn-{number}\" id=\"{}\">{number}</a></sup>",
+                footnote_ref_id(number, occurrence)
+            ),
         }
     }
 }
@@ -49,15 +316,65 @@ impl<T> ToHtml for Vec<T>
 where
     T: ToHtml,
 {
-    fn to_html(self) -> String {
+    fn to_html_with(self, grammars: &Grammars) -> String {
         let mut rendered = String::new();
         for i in self {
-            rendered.push_str(&i.to_html());
+            rendered.push_str(&i.to_html_with(grammars));
         }
         rendered
     }
 }
 
+/// Escapes text content, so it can't open or close tags or start character references.
+pub(crate) fn escape_text(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes a quoted attribute value, so it can't break out of its quotes.
+pub(crate) fn escape_attr(s: &str) -> String {
+    escape_text(s).replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+/// Renders an optional ` title="…"` attribute.
+fn title_attr(title: Option<String>) -> String {
+    title
+        .map(|t| format!(" title=\"{}\"", escape_attr(&t)))
+        .unwrap_or_default()
+}
+
+/// Replaces URLs with script-capable schemes (`javascript:`, `vbscript:`, non-image `data:`)
+/// with `#`. Browsers ignore whitespace and control characters inside the scheme, so those are
+/// dropped before comparing.
+fn sanitize_url(url: &str) -> &str {
+    let Some((scheme, rest)) = url.split_once(':') else {
+        return url;
+    };
+    if scheme.contains(['/', '?', '#']) {
+        // the colon is in the path, query or fragment of a relative URL
+        return url;
+    }
+    let scheme: String = scheme
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let unsafe_scheme = match scheme.as_str() {
+        "javascript" | "vbscript" => true,
+        "data" => !rest.trim_start().to_ascii_lowercase().starts_with("image/"),
+        _ => false,
+    };
+    if unsafe_scheme { "#" } else { url }
+}
+
 // --------------------
 // TESTS
 // --------------------
@@ -65,13 +382,17 @@ where
 #[cfg(test)]
 mod unit_test {
     use super::*;
+    use crate::ast::Metadata;
+    use std::collections::BTreeMap;
 
     #[test]
     fn single_header() {
         let ast = Document {
+            metadata: Metadata::default(),
             blocks: vec![Block::Heading {
                 level: 1,
                 content: vec![Inline::Text("Heading 1".to_string())],
+                id: "heading-1".to_string(),
             }],
         };
 
@@ -79,19 +400,21 @@ mod unit_test {
 
         assert_eq!(
             html,
-            "<!doctype html><html lang=en><head></head><body><h1>Heading 1</h1></body></html>"
+            "<!doctype html><html lang=en><head></head><body><h1 id=\"heading-1\">Heading 1</h1></body></html>"
         );
     }
 
     #[test]
     fn inline_bold_header() {
         let ast = Document {
+            metadata: Metadata::default(),
             blocks: vec![Block::Heading {
                 level: 1,
                 content: vec![
                     Inline::Bold(vec![Inline::Text("Bold".to_string())]),
                     Inline::Text(" heading 1".to_string()),
                 ],
+                id: String::new(),
             }],
         };
 
@@ -106,6 +429,7 @@ mod unit_test {
     #[test]
     fn headings_and_paragraph_nested_code() {
         let ast = Document {
+            metadata: Metadata::default(),
             blocks: vec![
                 Block::Heading {
                     level: 1,
@@ -113,10 +437,12 @@ mod unit_test {
                         Inline::Bold(vec![Inline::Text("Bold".to_string())]),
                         Inline::Text(" heading 1".to_string()),
                     ],
+                    id: "bold-heading-1".to_string(),
                 },
                 Block::Heading {
                     level: 2,
                     content: vec![Inline::Text("Heading 2".to_string())],
+                    id: "heading-2".to_string(),
                 },
                 Block::Paragraph(vec![
                     Inline::Text("run ".to_string()),
@@ -130,9 +456,129 @@ mod unit_test {
 
         assert_eq!(
             html,
-            "<!doctype html><html lang=en><head></head><body><h1><b>Bold</b> heading 1</h1><h2>Heading 2</h2><p>run <code>sudo rm -rf /</code> on your computer</p></body></html>"
+            "<!doctype html><html lang=en><head></head><body><h1 id=\"bold-heading-1\"><b>Bold</b> heading 1</h1><h2 id=\"heading-2\">Heading 2</h2><p>run <code>sudo rm -rf /</code> on your computer</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn escape_text_content() {
+        let ast = Block::Paragraph(vec![Inline::Text("a < b && c > \"d\"".to_string())]);
+
+        assert_eq!(ast.to_html(), "<p>a &lt; b &amp;&amp; c &gt; \"d\"</p>");
+    }
+
+    #[test]
+    fn syntax_extensions() {
+        let ast = vec![
+            Inline::Strikethrough(vec![Inline::Text("a".to_string())]),
+            Inline::Highlight(vec![Inline::Text("b".to_string())]),
+            Inline::Subscript(vec![Inline::Text("c".to_string())]),
+            Inline::Superscript(vec![Inline::Text("d".to_string())]),
+            Inline::Kbd("<".to_string()),
+        ];
+
+        assert_eq!(
+            ast.to_html(),
+            "<del>a</del><mark>b</mark><sub>c</sub><sup>d</sup><kbd>&lt;</kbd>"
         );
     }
+
+    #[test]
+    fn escape_inline_code() {
+        let ast = Inline::Code("Vec<&str>".to_string());
+
+        assert_eq!(ast.to_html(), "<code>Vec&lt;&amp;str&gt;</code>");
+    }
+
+    #[test]
+    fn escape_code_block() {
+        let ast = Block::Code {
+            language: None,
+            content: "</code></pre><script>alert(1)</script>\n".to_string(),
+            attributes: BTreeMap::new(),
+        };
+
+        assert_eq!(
+            ast.to_html(),
+            "<pre><code>&lt;/code&gt;&lt;/pre&gt;&lt;script&gt;alert(1)&lt;/script&gt;\n</code></pre>"
+        );
+    }
+
+    #[test]
+    fn highlighted_code_block() {
+        let code = |language: &str| Block::Code {
+            language: Some(language.to_string()),
+            content: "let s = \"</pre>\";\n".to_string(),
+            attributes: BTreeMap::new(),
+        };
+
+        assert_eq!(
+            code("rust").to_html(),
+            "<pre><code class=\"language-rust\"><span class=\"keyword\">let</span> s = <span class=\"string\">\"&lt;/pre&gt;\"</span>;\n</code></pre>"
+        );
+        // unknown languages are escaped as they are
+        assert_eq!(
+            code("x\"y").to_html(),
+            "<pre><code class=\"language-x&quot;y\">let s = \"&lt;/pre&gt;\";\n</code></pre>"
+        );
+    }
+
+    #[test]
+    fn code_block_title_and_lines() {
+        let code = |attributes: &[(&str, &str)]| Block::Code {
+            language: Some("rust".to_string()),
+            content: "let a = 1;\n// <b>\nlet b = 2;\n".to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+
+        assert_eq!(
+            code(&[("title", "<main.rs>")]).to_html(),
+            "<figure class=\"code\"><figcaption>&lt;main.rs&gt;</figcaption><pre><code class=\"language-rust\"><span class=\"keyword\">let</span> a = <span class=\"number\">1</span>;\n<span class=\"comment\">// &lt;b&gt;</span>\n<span class=\"keyword\">let</span> b = <span class=\"number\">2</span>;\n</code></pre></figure>"
+        );
+        assert_eq!(
+            code(&[("linenos", ""), ("hl_lines", "2-3")]).to_html(),
+            "<pre><code class=\"language-rust\"><span class=\"line\"><span class=\"lineno\">1</span><span class=\"keyword\">let</span> a = <span class=\"number\">1</span>;</span>\n<span class=\"line hl\"><span class=\"lineno\">2</span><span class=\"comment\">// &lt;b&gt;</span></span>\n<span class=\"line hl\"><span class=\"lineno\">3</span><span class=\"keyword\">let</span> b = <span class=\"number\">2</span>;</span>\n</code></pre>"
+        );
+        assert_eq!(
+            code(&[("linenos", "false"), ("hl_lines", "1")]).to_html(),
+            "<pre><code class=\"language-rust\"><span class=\"line hl\"><span class=\"keyword\">let</span> a = <span class=\"number\">1</span>;</span>\n<span class=\"line\"><span class=\"comment\">// &lt;b&gt;</span></span>\n<span class=\"line\"><span class=\"keyword\">let</span> b = <span class=\"number\">2</span>;</span>\n</code></pre>"
+        );
+    }
+
+    #[test]
+    fn escape_link_attribute() {
+        let ast = Inline::Link {
+            text: vec![Inline::Text("x".to_string())],
+            href: "https://example.com/\" onmouseover=\"alert('1')".to_string(),
+            title: None,
+        };
+
+        assert_eq!(
+            ast.to_html(),
+            "<a href=\"https://example.com/&quot; onmouseover=&quot;alert(&#39;1&#39;)\">x</a>"
+        );
+    }
+
+    #[test]
+    fn sanitize_script_urls() {
+        assert_eq!(sanitize_url("javascript:alert(1)"), "#");
+        assert_eq!(sanitize_url(" JaVa\tScRiPt:alert(1)"), "#");
+        assert_eq!(sanitize_url("vbscript:msgbox"), "#");
+        assert_eq!(sanitize_url("data:text/html;base64,AAAA"), "#");
+        assert_eq!(
+            sanitize_url("data:image/png;base64,AAAA"),
+            "data:image/png;base64,AAAA"
+        );
+        assert_eq!(sanitize_url("https://example.com"), "https://example.com");
+        assert_eq!(
+            sanitize_url("mailto:me@example.com"),
+            "mailto:me@example.com"
+        );
+        assert_eq!(sanitize_url("./notes/a:b.html"), "./notes/a:b.html");
+    }
 }
 
 #[cfg(test)]
@@ -151,7 +597,7 @@ mod convert_md_to_html_test {
 
         assert_eq!(
             html,
-            "<!doctype html><html lang=en><head></head><body><h1>Header 1</h1></body></html>"
+            "<!doctype html><html lang=en><head></head><body><h1 id=\"header-1\">Header 1</h1></body></html>"
         );
     }
 
@@ -174,7 +620,142 @@ mod convert_md_to_html_test {
 
         assert_eq!(
             html,
-            "<!doctype html><html lang=en><head></head><body><h1><b>Bold</b> header 1</h1><h2>Header 2</h2><p>run <code>sudo rm -rf /</code> on your computer</p></body></html>"
+            "<!doctype html><html lang=en><head></head><body><h1 id=\"bold-header-1\"><b>Bold</b> header 1</h1><h2 id=\"header-2\">Header 2</h2><p>run <code>sudo rm -rf /</code> on your computer</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn nested_unordered_list() {
+        let md = "- a\n  - b\n- c";
+        let ast = match parse(md) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let html = ast.to_html();
+
+        assert_eq!(
+            html,
+            "<!doctype html><html lang=en><head></head><body><ul><li>a<ul><li>b</li></ul></li><li>c</li></ul></body></html>"
+        );
+    }
+
+    #[test]
+    fn task_list() {
+        let md = "- [x] done\n- [ ] *open*\n- plain";
+        let ast = match parse(md) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let html = ast.blocks.to_html();
+
+        assert_eq!(
+            html,
+            "<ul><li class=\"task\"><input type=\"checkbox\" disabled checked> done</li><li class=\"task\"><input type=\"checkbox\" disabled> <b>open</b></li><li>plain</li></ul>"
+        );
+    }
+
+    #[test]
+    fn ordered_list_start() {
+        let md = "1. a\n2. b\n\n7) c";
+        let ast = match parse(md) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let html = ast.to_html();
+
+        assert_eq!(
+            html,
+            "<!doctype html><html lang=en><head></head><body><ol><li>a</li><li>b</li></ol><ol start=\"7\"><li>c</li></ol></body></html>"
+        );
+    }
+
+    #[test]
+    fn nested_quote() {
+        let md = "> a\n>\n> > b";
+        let ast = match parse(md) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let html = ast.to_html();
+
+        assert_eq!(
+            html,
+            "<!doctype html><html lang=en><head></head><body><blockquote><p>a</p><blockquote><p>b</p></blockquote></blockquote></body></html>"
+        );
+    }
+
+    #[test]
+    fn paragraph_with_hard_break() {
+        let md = "roses are red  \nviolets are blue\nsugar is sweet";
+        let ast = match parse(md) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let html = ast.to_html();
+
+        assert_eq!(
+            html,
+            "<!doctype html><html lang=en><head></head><body><p>roses are red<br>violets are blue\nsugar is sweet</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn aligned_table() {
+        let md = "| a | b | c |\n|:--|:-:|--:|\n| 1 | *2* | 3 |";
+        let ast = match parse(md) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let html = ast.to_html();
+
+        assert_eq!(
+            html,
+            "<!doctype html><html lang=en><head></head><body><table><thead><tr><th style=\"text-align: left\">a</th><th style=\"text-align: center\">b</th><th style=\"text-align: right\">c</th></tr></thead><tbody><tr><td style=\"text-align: left\">1</td><td style=\"text-align: center\"><b>2</b></td><td style=\"text-align: right\">3</td></tr></tbody></table></body></html>"
+        );
+    }
+
+    #[test]
+    fn image_with_escaped_attributes() {
+        let md = "![\"quoted\" <alt>](shot.png \"a & b\")";
+        let ast = match parse(md) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let html = ast.to_html();
+
+        assert_eq!(
+            html,
+            "<!doctype html><html lang=en><head></head><body><p><img src=\"shot.png\" alt=\"&quot;quoted&quot; &lt;alt&gt;\" title=\"a &amp; b\"></p></body></html>"
+        );
+    }
+
+    #[test]
+    fn footnotes() {
+        let md = "A[^1] b[^note] c[^1].\n\n[^1]: One.\n[^note]: Two.\n\n    ```\n    code\n    ```";
+        let ast = match parse(md) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let html = ast.blocks.to_html();
+
+        assert_eq!(
+            html,
+            "<p>A<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1\">1</a></sup> b<sup class=\"footnote-ref\"><a href=\"#fn-2\" id=\"fnref-2\">2</a></sup> c<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1-2\">1</a></sup>.</p><section class=\"footnotes\"><ol><li id=\"fn-1\"><p>One. <a href=\"#fnref-1\" class=\"footnote-backref\">↩</a> <a href=\"#fnref-1-2\" class=\"footnote-backref\">↩<sup>2</sup></a></p></li><li id=\"fn-2\"><p>Two.</p><pre><code>code\n</code></pre> <a href=\"#fnref-2\" class=\"footnote-backref\">↩</a></li></ol></section>"
+        );
+    }
+
+    #[test]
+    fn table_of_contents() {
+        let md = "[TOC]\n\n# Intro\n## Usage <tips>\n# Intro";
+        let ast = match parse(md) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let html = ast.to_html();
+
+        assert_eq!(
+            html,
+            "<!doctype html><html lang=en><head></head><body><nav class=\"toc\"><ul><li><a href=\"#intro\">Intro</a><ul><li><a href=\"#usage-tips\">Usage &lt;tips&gt;</a></li></ul></li><li><a href=\"#intro-1\">Intro</a></li></ul></nav><h1 id=\"intro\">Intro</h1><h2 id=\"usage-tips\">Usage &lt;tips&gt;</h2><h1 id=\"intro-1\">Intro</h1></body></html>"
         );
     }
 }
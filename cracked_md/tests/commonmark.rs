@@ -0,0 +1,147 @@
+//! Runs the examples of the CommonMark spec through the CommonMark dialect.
+//!
+//! `commonmark/spec.txt` is the spec (version 0.28, see `commonmark/LICENSE`), and
+//! `commonmark/passing` lists the numbers of the examples known to pass. The test fails when one
+//! of them stops passing. After making more of them pass, update the list with
+//! `UPDATE_SPEC_PASSING=1 cargo test -p cracked_md --test commonmark`.
+
+use cracked_md::{
+    parser::{Dialect, Extensions, ParseOptions, parse_with},
+    to_html::ToHtml,
+};
+use std::{collections::BTreeSet, fs, path::Path};
+
+const FENCE: &str = "````````````````````````````````";
+
+struct Example {
+    number: usize,
+    markdown: String,
+    html: String,
+}
+
+/// Reads the examples, blocks of Markdown and the expected HTML separated by a `.` line. Tabs
+/// are written as `→` in the spec.
+fn examples(spec: &str) -> Vec<Example> {
+    let mut examples = Vec::new();
+    let mut lines = spec.lines();
+    while let Some(line) = lines.next() {
+        if line != format!("{FENCE} example") {
+            continue;
+        }
+        let mut markdown = String::new();
+        let mut html = String::new();
+        let mut in_html = false;
+        for line in lines.by_ref() {
+            match line {
+                FENCE => break,
+                "." if !in_html => in_html = true,
+                _ => {
+                    let part = if in_html { &mut html } else { &mut markdown };
+                    part.push_str(&line.replace('→', "\t"));
+                    part.push('\n');
+                }
+            }
+        }
+        examples.push(Example {
+            number: examples.len() + 1,
+            markdown,
+            html,
+        });
+    }
+    examples
+}
+
+/// Renders the blocks of the document, without the page around them. The syntax extensions are
+/// off, they are not part of the spec.
+fn render(markdown: &str) -> String {
+    let options = ParseOptions {
+        dialect: Dialect::CommonMark,
+        extensions: Extensions::NONE,
+        ..ParseOptions::default()
+    };
+    match parse_with(markdown, &options) {
+        Ok(parsed) => parsed.document.blocks.to_html(),
+        Err(e) => format!("error: {e}"),
+    }
+}
+
+/// Removes the differences in how the same HTML is written: line breaks between tags, void
+/// tags closed with `/>`, `<b>`/`<i>` for `<strong>`/`<em>`, quotes as entities, and the ids
+/// gravel gives to headings.
+fn normalize(html: &str) -> String {
+    let mut html = html
+        .replace(" />", ">")
+        .replace("&quot;", "\"")
+        .replace("<b>", "<strong>")
+        .replace("</b>", "</strong>")
+        .replace("<i>", "<em>")
+        .replace("</i>", "</em>");
+    let mut start = 0;
+    while let Some(h) = html[start..].find("<h").map(|h| start + h + 3) {
+        if html[h..].starts_with(" id=\"") {
+            let end = html[h + 5..]
+                .find('"')
+                .map_or(html.len(), |e| h + 5 + e + 1);
+            html.replace_range(h..end, "");
+        }
+        start = h.min(html.len());
+    }
+    let mut normalized = String::new();
+    let mut chars = html.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        let between_tags = normalized.ends_with('>') || chars.peek() == Some(&'<');
+        if !(c == '\n' && between_tags) {
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+#[test]
+fn commonmark_spec() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/commonmark");
+    let spec = fs::read_to_string(dir.join("spec.txt")).unwrap();
+    let examples = examples(&spec);
+
+    let passing: BTreeSet<usize> = examples
+        .iter()
+        .filter(|e| normalize(&render(&e.markdown)) == normalize(&e.html))
+        .map(|e| e.number)
+        .collect();
+    println!(
+        "{}/{} CommonMark spec examples pass ({:.1}%)",
+        passing.len(),
+        examples.len(),
+        passing.len() as f64 * 100.0 / examples.len() as f64
+    );
+
+    let list = dir.join("passing");
+    if std::env::var_os("UPDATE_SPEC_PASSING").is_some() {
+        let numbers: String = passing.iter().map(|n| format!("{n}\n")).collect();
+        fs::write(&list, numbers).unwrap();
+        return;
+    }
+    let expected: BTreeSet<usize> = fs::read_to_string(&list)
+        .unwrap()
+        .lines()
+        .map(|n| n.parse().unwrap())
+        .collect();
+    let regressed: Vec<_> = examples
+        .iter()
+        .filter(|e| expected.contains(&e.number) && !passing.contains(&e.number))
+        .collect();
+    for e in &regressed {
+        println!(
+            "example {} regressed\n--- markdown\n{}--- expected\n{}--- got\n{}\n",
+            e.number,
+            e.markdown,
+            e.html,
+            render(&e.markdown)
+        );
+    }
+    assert!(
+        regressed.is_empty(),
+        "{} examples regressed",
+        regressed.len()
+    );
+}
@@ -2,16 +2,32 @@
 
 // todo: refactor to <command> <subcommand> [<options>]
 
+use cracked_md::GenerateOptions;
+use cracked_md::ast::Metadata;
+use cracked_md::parser::{Dialect, Extensions, parse_metadata};
 use slogger::{LOG_LEVEL, Level, log};
 
 use crate::error::Error;
 use std::env::Args;
+use std::fs;
 use std::net::Ipv4Addr;
 use std::path::{Path, PathBuf};
 
 pub enum Command {
-    Generate { force: bool, single: bool },
-    Serve { addr: Ipv4Addr, port: u16 },
+    Generate {
+        force: bool,
+        single: bool,
+        full: bool,
+        /// Files built at the same time, one per CPU core if not set.
+        jobs: Option<usize>,
+        keep_going: bool,
+        /// Fail on Markdown problems instead of warning about them.
+        strict: bool,
+    },
+    Serve {
+        addr: Ipv4Addr,
+        port: u16,
+    },
     Init,
 }
 
@@ -20,6 +36,10 @@ impl Default for Command {
         Self::Generate {
             force: true,
             single: false,
+            full: false,
+            jobs: None,
+            keep_going: false,
+            strict: false,
         }
     }
 }
@@ -69,14 +89,28 @@ impl TryFrom<Args> for Command {
             }
             comm = Command::Init;
         }
-        // `gravel` command
-        for a in value {
-            match a.as_str() {
-                "-s" => {
-                    comm = Command::Generate {
-                        force: true,
-                        single: true,
-                    }
+        // `gravel` command, where the first argument is already an option
+        let first_option = command.filter(|c| c != "serve" && c != "init");
+        let mut options = first_option.into_iter().chain(value);
+        while let Some(a) = options.next() {
+            match (&mut comm, a.as_str()) {
+                (Command::Generate { single, .. }, "-s") => *single = true,
+                // rebuild everything instead of only the changed files
+                (Command::Generate { full, .. }, "--full") => *full = true,
+                // report the errors of all pages instead of stopping at the first
+                (Command::Generate { keep_going, .. }, "-k" | "--keep-going") => {
+                    *keep_going = true;
+                }
+                (Command::Generate { strict, .. }, "--strict") => *strict = true,
+                (Command::Generate { jobs, .. }, "-j") => {
+                    let jobs_str = options.next().ok_or(Error::CommandLineArgsParse(
+                        "Missing argument after `-j`. Expected number of jobs.".to_string(),
+                    ))?;
+                    *jobs = Some(jobs_str.parse().ok().filter(|&n| n > 0).ok_or(
+                        Error::CommandLineArgsParse(
+                            "Parsing number of jobs failed, expected at least 1".to_string(),
+                        ),
+                    )?);
                 }
                 _ => Err(Error::CommandLineArgsParse(format!(
                     "Unknown argument: `{a}`"
@@ -88,10 +122,35 @@ impl TryFrom<Args> for Command {
     }
 }
 
+/// Keys of the TOML file for build settings, which templates don't get as `site` variables: the
+/// ones read by [`ProgramConfig`] and the defaults of the command line options.
+const BUILD_KEYS: [&str; 7] = [
+    "markdown_extensions",
+    "dialect",
+    "syntax_extensions",
+    "jobs",
+    "force",
+    "strict",
+    "keep_going",
+];
+
 #[allow(unused)]
 pub struct ProgramConfig {
     pub outdir: PathBuf,
     pub indir: PathBuf,
+    pub templates: PathBuf,
+    /// Grammar definitions for highlighting code blocks, besides the built-in ones.
+    pub grammars: PathBuf,
+    /// Extensions of the files turned to HTML, set by `markdown_extensions` in the TOML file.
+    pub markdown_extensions: Vec<String>,
+    /// The flavor of Markdown, set by `dialect` in the TOML file: `gravel` or `commonmark`.
+    pub dialect: Dialect,
+    /// Inline syntax on top of the dialect, all but bare URLs by default. Set by
+    /// `syntax_extensions` in the TOML file, an array of names like `"strikethrough"`.
+    pub syntax_extensions: Extensions,
+    /// Site variables for templates, from the `key = value` lines of the TOML file other than
+    /// the [`BUILD_KEYS`].
+    pub site: Metadata,
     pub command: Command,
     pub verbose: bool,
 }
@@ -101,6 +160,12 @@ impl Default for ProgramConfig {
         Self {
             indir: PathBuf::from("./pebbles"),
             outdir: PathBuf::from("./site"),
+            templates: PathBuf::from("./templates"),
+            grammars: PathBuf::from("./grammars"),
+            markdown_extensions: GenerateOptions::default().markdown_extensions,
+            dialect: Dialect::default(),
+            syntax_extensions: Extensions::default(),
+            site: Metadata::default(),
             command: Command::default(),
             verbose: true,
         }
@@ -108,15 +173,174 @@ impl Default for ProgramConfig {
 }
 
 impl ProgramConfig {
-    pub fn new<P: AsRef<Path>>(_toml_file: P, args: Args) -> Result<Self, Error> {
-        let conf = Self {
+    pub fn new<P: AsRef<Path>>(toml_file: P, args: Args) -> Result<Self, Error> {
+        let mut conf = Self {
             command: args.try_into()?,
             ..Default::default()
         };
         LOG_LEVEL.get_or_init(|| Level::Debug);
-        log!(Level::Warn, "TOML parsing not implemented, skipping");
+
+        let toml_file = toml_file.as_ref();
+        if toml_file.is_file() {
+            let toml = fs::read_to_string(toml_file).map_err(|e| {
+                Error::Config(format!("Reading `{}` failed: {e}", toml_file.display()))
+            })?;
+            conf.read_toml(&toml, toml_file)?;
+        } else {
+            log!(
+                Level::Debug,
+                "Config file `{}` not found, using defaults",
+                toml_file.display()
+            );
+        }
         Ok(conf)
     }
+
+    /// Reads the settings and site variables of the TOML file.
+    fn read_toml(&mut self, toml: &str, toml_file: &Path) -> Result<(), Error> {
+        let mut settings = parse_metadata(toml)
+            .map_err(|e| cracked_md::Error::from(e.set_file(toml_file.into()).set_source(toml)))?;
+        if let Some(extensions) = settings.get("markdown_extensions") {
+            self.markdown_extensions = extensions
+                .as_array()
+                .and_then(|exts| {
+                    exts.iter()
+                        .map(|ext| ext.as_str().map(str::to_string))
+                        .collect::<Option<_>>()
+                })
+                .ok_or(Error::Config(
+                    "`markdown_extensions` must be an array of strings".to_string(),
+                ))?;
+        }
+        if let Some(dialect) = settings.get("dialect") {
+            self.dialect = dialect
+                .as_str()
+                .and_then(Dialect::from_name)
+                .ok_or(Error::Config(
+                    "`dialect` must be \"gravel\" or \"commonmark\"".to_string(),
+                ))?;
+        }
+        if let Some(extensions) = settings.get("syntax_extensions") {
+            let names = extensions
+                .as_array()
+                .and_then(|names| names.iter().map(|n| n.as_str()).collect::<Option<Vec<_>>>());
+            self.syntax_extensions = names.and_then(Extensions::from_names).ok_or(
+                Error::Config(
+                    "`syntax_extensions` must be an array of \"strikethrough\", \"highlight\", \"subscript\", \"superscript\", \"kbd\" and \"bare_urls\"".to_string(),
+                ),
+            )?;
+        }
+        // the command line options are only ever turned on, so they win over the file
+        if let Command::Generate {
+            force,
+            jobs,
+            keep_going,
+            strict,
+            ..
+        } = &mut self.command
+        {
+            let flag = |key: &str| {
+                settings.get(key).map_or(Ok(false), |v| {
+                    v.as_bool()
+                        .ok_or(Error::Config(format!("`{key}` must be true or false")))
+                })
+            };
+            if let Some(f) = settings.get("force") {
+                *force = f
+                    .as_bool()
+                    .ok_or(Error::Config("`force` must be true or false".to_string()))?;
+            }
+            *keep_going |= flag("keep_going")?;
+            *strict |= flag("strict")?;
+            if let Some(j) = settings.get("jobs")
+                && jobs.is_none()
+            {
+                *jobs = Some(
+                    j.as_integer()
+                        .and_then(|n| usize::try_from(n).ok())
+                        .filter(|&n| n > 0)
+                        .ok_or(Error::Config(
+                            "`jobs` must be a number of at least 1".to_string(),
+                        ))?,
+                );
+            }
+        }
+        for key in BUILD_KEYS {
+            settings.remove(key);
+        }
+        self.site = settings;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use cracked_md::{ast::Value, parser::Dialect};
+
+    use super::{Command, ProgramConfig};
+    use crate::error::Error;
+
+    #[test]
+    fn build_settings_are_not_site_variables() {
+        let mut conf = ProgramConfig::default();
+        let toml = "title = \"My site\"\ndialect = \"commonmark\"\njobs = 4\nstrict = true\nauthor = \"me\"";
+        conf.read_toml(toml, Path::new("gravel.toml")).unwrap();
+
+        assert_eq!(conf.dialect, Dialect::CommonMark);
+        let keys: Vec<_> = conf.site.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["author", "title"]);
+        assert_eq!(
+            conf.site.get("title"),
+            Some(&Value::String("My site".to_string()))
+        );
+    }
+
+    #[test]
+    fn build_settings_apply_unless_given_on_the_command_line() {
+        let toml = "jobs = 4\nstrict = true\nkeep_going = true\nforce = false";
+        let mut conf = ProgramConfig::default();
+        conf.read_toml(toml, Path::new("gravel.toml")).unwrap();
+        let Command::Generate {
+            force,
+            jobs,
+            keep_going,
+            strict,
+            ..
+        } = conf.command
+        else {
+            panic!("expected the generate command");
+        };
+        assert!(!force && keep_going && strict);
+        assert_eq!(jobs, Some(4));
+
+        let mut conf = ProgramConfig::default();
+        if let Command::Generate { jobs, .. } = &mut conf.command {
+            *jobs = Some(2);
+        }
+        conf.read_toml(toml, Path::new("gravel.toml")).unwrap();
+        assert!(matches!(
+            conf.command,
+            Command::Generate { jobs: Some(2), .. }
+        ));
+    }
+
+    #[test]
+    fn wrong_build_setting_types() {
+        for toml in [
+            "markdown_extensions = \"md\"",
+            "markdown_extensions = [1]",
+            "jobs = 0",
+            "jobs = \"4\"",
+            "strict = \"yes\"",
+            "force = 1",
+        ] {
+            let mut conf = ProgramConfig::default();
+            let result = conf.read_toml(toml, Path::new("gravel.toml"));
+            assert!(matches!(result, Err(Error::Config(_))), "{toml}");
+        }
+    }
 }
 
 /*
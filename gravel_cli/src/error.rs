@@ -5,6 +5,7 @@ pub enum Error {
     Server(stdsrv::error::Error),
     MdParse(cracked_md::Error),
     CommandLineArgsParse(String),
+    Config(String),
 }
 
 impl Display for Error {
@@ -12,7 +13,7 @@ impl Display for Error {
         match self {
             Error::Server(e) => e.fmt(f),
             Error::MdParse(e) => e.fmt(f),
-            Error::CommandLineArgsParse(s) => write!(f, "{s}"),
+            Error::CommandLineArgsParse(s) | Error::Config(s) => write!(f, "{s}"),
         }
     }
 }
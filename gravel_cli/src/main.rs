@@ -3,7 +3,7 @@
 use std::process;
 
 use config::{Command, ProgramConfig};
-use cracked_md::generate;
+use cracked_md::{GenerateOptions, generate};
 use error::Error;
 use slogger::{Level, log};
 use stdsrv::serve;
@@ -20,10 +20,30 @@ fn run() -> Result<(), Error> {
         Command::Generate {
             force,
             single: false,
-        } => generate(&conf.indir, &conf.outdir, force)?,
+            full,
+            jobs,
+            keep_going,
+            strict,
+        } => {
+            let options = GenerateOptions {
+                force,
+                full,
+                markdown_extensions: conf.markdown_extensions,
+                templates: conf.templates.is_dir().then_some(conf.templates),
+                grammars: conf.grammars.is_dir().then_some(conf.grammars),
+                site: conf.site,
+                jobs: jobs.unwrap_or(GenerateOptions::default().jobs),
+                keep_going,
+                strict,
+                dialect: conf.dialect,
+                syntax_extensions: conf.syntax_extensions,
+            };
+            generate(&conf.indir, &conf.outdir, &options)?;
+        }
         Command::Generate {
             force: _f,
             single: true,
+            ..
         } => todo!("single file generation"),
     }
     Ok(())